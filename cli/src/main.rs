@@ -69,6 +69,27 @@ fn main() {
                 monitors: monitors.into_iter().map(unquote).collect(),
             }
         }
+        SubCmd::SetColor {
+            color,
+            to,
+            angle,
+            monitors,
+        } => IpcMessage::SetColor {
+            color,
+            to,
+            angle,
+            monitors: monitors.into_iter().map(unquote).collect(),
+        },
+        SubCmd::Favorite { monitors } => IpcMessage::Favorite {
+            monitors: monitors.into_iter().map(unquote).collect(),
+        },
+        SubCmd::Unfavorite { monitors } => IpcMessage::Unfavorite {
+            monitors: monitors.into_iter().map(unquote).collect(),
+        },
+        SubCmd::Ban { monitors } => IpcMessage::Ban {
+            monitors: monitors.into_iter().map(unquote).collect(),
+        },
+        SubCmd::Rescan => IpcMessage::Rescan,
     };
 
     conn.write_all(&serde_json::to_vec(&msg).unwrap()).unwrap();
@@ -125,13 +146,23 @@ fn main() {
                         status: String,
                         #[serde(rename = "duration_left", with = "humantime_serde")]
                         duration_left: Option<Duration>,
+                        excluded_images: usize,
+                        scanning: bool,
+                        cache_hits: u64,
+                        cache_misses: u64,
+                        rejected_images: Vec<(PathBuf, String)>,
                     }
                     let val = entries
                         .into_iter()
-                        .map(|(display, status, duration_left)| Item {
-                            display,
-                            status,
-                            duration_left: duration_left.map(clean_duration),
+                        .map(|entry| Item {
+                            display: entry.display,
+                            status: entry.status,
+                            duration_left: entry.duration_left.map(clean_duration),
+                            excluded_images: entry.excluded_images,
+                            scanning: entry.scanning,
+                            cache_hits: entry.cache_hits,
+                            cache_misses: entry.cache_misses,
+                            rejected_images: entry.rejected_images,
                         })
                         .collect::<Vec<_>>();
                     println!(
@@ -139,15 +170,39 @@ fn main() {
                         serde_json::to_string(&val).expect("json encoding to work")
                     );
                 } else {
-                    for (monitor, status, duration_left) in entries {
+                    for entry in entries {
+                        let mut details = Vec::new();
+                        if let Some(d) = entry.duration_left {
+                            details.push(format!(
+                                "{} left",
+                                humantime::format_duration(clean_duration(d))
+                            ));
+                        }
+                        if entry.excluded_images > 0 {
+                            details.push(format!("{} images excluded", entry.excluded_images));
+                        }
+                        if entry.scanning {
+                            details.push("scanning the directory".to_string());
+                        }
+                        if entry.cache_hits + entry.cache_misses > 0 {
+                            details.push(format!(
+                                "{} cache hits, {} cache misses",
+                                entry.cache_hits, entry.cache_misses
+                            ));
+                        }
                         println!(
-                            "{monitor}: {status}{}",
-                            if let Some(d) = duration_left {
-                                format!(" ({} left)", humantime::format_duration(clean_duration(d)))
-                            } else {
+                            "{}: {}{}",
+                            entry.display,
+                            entry.status,
+                            if details.is_empty() {
                                 "".to_string()
+                            } else {
+                                format!(" ({})", details.join(", "))
                             }
                         );
+                        for (path, reason) in entry.rejected_images {
+                            println!("  rejected {}: {reason}", path.display());
+                        }
                     }
                 }
             }
@@ -166,6 +221,12 @@ fn main() {
                 IpcError::ValidationError(msg) => {
                     eprintln!("{msg}")
                 }
+                IpcError::SaveError { path, error } => {
+                    eprintln!(
+                        "The change to {} could not be saved: {error}",
+                        path.display()
+                    )
+                }
             }
             std::process::exit(1);
         }
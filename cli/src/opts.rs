@@ -44,4 +44,25 @@ pub enum SubCmd {
         /// Monitors to set wallpaper on (if empty, sets on all monitors)
         monitors: Vec<String>,
     },
+    /// Show a solid color or a gradient until the next or previous wallpaper is requested
+    SetColor {
+        /// Color to show, as #RRGGBB
+        color: String,
+        /// Show a gradient from color to this one
+        #[clap(long)]
+        to: Option<String>,
+        /// Direction of the gradient in degrees, 0 goes upwards and 90 to the right
+        #[clap(long, requires = "to")]
+        angle: Option<f32>,
+        /// Monitors to set the color on (if empty, sets on all monitors)
+        monitors: Vec<String>,
+    },
+    /// Add the current wallpaper to the favorites
+    Favorite { monitors: Vec<String> },
+    /// Remove the current wallpaper from the favorites
+    Unfavorite { monitors: Vec<String> },
+    /// Never show the current wallpaper again and move to the next one
+    Ban { monitors: Vec<String> },
+    /// Read the wallpaper directories again, in case some changes have been missed
+    Rescan,
 }
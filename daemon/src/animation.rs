@@ -0,0 +1,246 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use color_eyre::Result;
+use image::{
+    codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder},
+    AnimationDecoder, DynamicImage, ImageDecoder, ImageFormat,
+};
+
+use crate::{
+    color::{self, ColorTransform},
+    image_loader::{DecodeLimits, TargetSize},
+};
+
+pub struct AnimationFrame {
+    pub image: Arc<DynamicImage>,
+    /// How long the frame is shown
+    pub delay: Duration,
+}
+
+/// The frames of an animated GIF, APNG or WebP
+pub struct Animation {
+    pub frames: Vec<AnimationFrame>,
+}
+
+impl Animation {
+    /// Frames shorter than this are shown for [Animation::DEFAULT_DELAY], like browsers do
+    const MIN_DELAY: Duration = Duration::from_millis(10);
+    const DEFAULT_DELAY: Duration = Duration::from_millis(100);
+
+    /// Decode all the frames of the image at `path`, converting their colors to `color_profile`.
+    /// Return None if the format doesn't support animations or the image is not animated
+    pub fn decode(
+        path: &Path,
+        format: ImageFormat,
+        size: TargetSize,
+        color_profile: Option<&Path>,
+        limits: &DecodeLimits,
+    ) -> Result<Option<Self>> {
+        let reader = || -> Result<BufReader<File>> { Ok(BufReader::new(File::open(path)?)) };
+        // Check the limits before decoding any frame
+        let prepare = |decoder: &mut dyn ImageDecoder| -> Result<Option<Vec<u8>>> {
+            let (width, height) = decoder.dimensions();
+            limits.check_dimensions(width, height)?;
+            decoder.set_limits(limits.image_limits())?;
+            Ok(decoder.icc_profile()?)
+        };
+        let (frames, icc_profile) = match format {
+            ImageFormat::Gif => {
+                let mut decoder = GifDecoder::new(reader()?)?;
+                let icc_profile = prepare(&mut decoder)?;
+                (decoder.into_frames(), icc_profile)
+            }
+            ImageFormat::Png => {
+                let mut decoder = PngDecoder::new(reader()?)?;
+                if !decoder.is_apng()? {
+                    return Ok(None);
+                }
+                let icc_profile = prepare(&mut decoder)?;
+                (decoder.apng()?.into_frames(), icc_profile)
+            }
+            ImageFormat::WebP => {
+                let mut decoder = WebPDecoder::new(reader()?)?;
+                if !decoder.has_animation() {
+                    return Ok(None);
+                }
+                let icc_profile = prepare(&mut decoder)?;
+                (decoder.into_frames(), icc_profile)
+            }
+            _ => return Ok(None),
+        };
+        let mut transform = ColorTransform::new(icc_profile.as_deref(), color_profile)?;
+        // All the frames are kept in memory
+        let mut used = 0;
+        let frames = frames
+            .map(|frame| {
+                let frame = frame?;
+                let delay = match Duration::from(frame.delay()) {
+                    delay if delay < Self::MIN_DELAY => Self::DEFAULT_DELAY,
+                    delay => delay,
+                };
+                let image = size.resize(DynamicImage::ImageRgba8(frame.into_buffer()));
+                let image = color::convert(transform.as_mut(), image)?;
+                used += image.as_bytes().len() as u64;
+                limits.check_memory(used)?;
+                Ok(AnimationFrame {
+                    image: Arc::new(image),
+                    delay,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok((!frames.is_empty()).then_some(Self { frames }))
+    }
+
+    /// Memory used by all the frames, in bytes
+    pub fn size(&self) -> usize {
+        self.frames
+            .iter()
+            .map(|frame| frame.image.as_bytes().len())
+            .sum()
+    }
+}
+
+/// Play an [Animation] on a display
+pub struct AnimationPlayer {
+    animation: Arc<Animation>,
+    frame: usize,
+    /// When the current frame should have been shown
+    shown_at: Instant,
+    /// When the last frame has been returned by [AnimationPlayer::advance]
+    uploaded_at: Instant,
+    /// The animation is not advancing, because the display has been paused
+    pub stopped: bool,
+}
+
+impl AnimationPlayer {
+    pub fn new(animation: Arc<Animation>, now: Instant) -> Self {
+        Self {
+            animation,
+            frame: 0,
+            shown_at: now,
+            uploaded_at: now,
+            stopped: false,
+        }
+    }
+
+    /// How long `frame` is shown when playing at `speed`
+    fn delay(&self, frame: usize, speed: f32) -> Duration {
+        self.animation.frames[frame].delay.div_f32(speed)
+    }
+
+    /// Minimum time between two frames returned by [AnimationPlayer::advance]
+    fn upload_interval(max_fps: Option<u32>) -> Duration {
+        max_fps.map_or(Duration::ZERO, |max_fps| Duration::from_secs(1) / max_fps)
+    }
+
+    /// When [AnimationPlayer::advance] will return the next frame
+    pub fn next_due(&self, speed: f32, max_fps: Option<u32>) -> Instant {
+        let shown_at = self.shown_at + self.delay(self.frame, speed);
+        shown_at.max(self.uploaded_at + Self::upload_interval(max_fps))
+    }
+
+    /// Move to the last frame that is due, skipping the ones there was no time to show.
+    /// With `max_fps`, a new frame is returned at most `max_fps` times per second, the animation
+    /// keeps its speed anyway. Return the new frame, if any
+    pub fn advance(
+        &mut self,
+        now: Instant,
+        speed: f32,
+        max_fps: Option<u32>,
+    ) -> Option<Arc<DynamicImage>> {
+        let interval = Self::upload_interval(max_fps);
+        let upload_due = self.uploaded_at + interval;
+        if now < upload_due {
+            return None;
+        }
+        let frames = self.animation.frames.len();
+        // Skip the whole loops first (e.g. the display was hidden), keeping the animation in time
+        let duration: Duration = (0..frames).map(|frame| self.delay(frame, speed)).sum();
+        let late = now.saturating_duration_since(self.shown_at).as_nanos();
+        let mut advanced = late >= duration.as_nanos() && !duration.is_zero();
+        if advanced {
+            self.shown_at += Duration::from_nanos((late - late % duration.as_nanos()) as u64);
+        }
+        for _ in 0..frames {
+            let due = self.shown_at + self.delay(self.frame, speed);
+            if now < due {
+                break;
+            }
+            self.frame = (self.frame + 1) % frames;
+            self.shown_at = due;
+            advanced = true;
+        }
+        if !advanced {
+            return None;
+        }
+        // Keep the pace of the uploads, unless they are very late
+        self.uploaded_at = if now - upload_due < interval {
+            upload_due
+        } else {
+            now
+        };
+        Some(self.animation.frames[self.frame].image.clone())
+    }
+
+    /// Start playing again from the current frame
+    pub fn restart(&mut self, now: Instant) {
+        self.shown_at = now;
+        self.uploaded_at = now;
+        self.stopped = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_advance() {
+        let frame = |delay| AnimationFrame {
+            image: Arc::new(DynamicImage::new_rgba8(1, 1)),
+            delay: Duration::from_millis(delay),
+        };
+        let animation = Arc::new(Animation {
+            frames: vec![frame(100), frame(200)],
+        });
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let mut player = AnimationPlayer::new(animation, start);
+
+        assert!(player.advance(ms(50), 1.0, None).is_none());
+        assert!(player.advance(ms(110), 1.0, None).is_some());
+        assert_eq!(player.frame, 1);
+        // The delays are divided by the speed, allow for rounding
+        assert!((ms(299)..ms(301)).contains(&player.next_due(1.0, None)));
+        // The frame has been shown late, the next one keeps the pace
+        assert!(player.advance(ms(290), 1.0, None).is_none());
+        assert!(player.advance(ms(301), 1.0, None).is_some());
+        assert_eq!(player.frame, 0);
+
+        // Twice as fast
+        assert!(player.advance(ms(351), 2.0, None).is_some());
+        assert_eq!(player.frame, 1);
+        // At most 2 frames per second
+        assert!(player.advance(ms(800), 2.0, Some(2)).is_none());
+        assert!(player.advance(ms(852), 2.0, Some(2)).is_some());
+        assert_eq!(player.frame, 1);
+
+        // 40 frames per second shown at most 10 times per second, at the same speed
+        let animation = Arc::new(Animation {
+            frames: (0..5).map(|_| frame(25)).collect(),
+        });
+        let mut player = AnimationPlayer::new(animation, start);
+        assert!(player.advance(ms(100), 1.0, Some(10)).is_some());
+        assert_eq!(player.frame, 4);
+        assert_eq!(player.next_due(1.0, Some(10)), ms(200));
+        assert!(player.advance(ms(150), 1.0, Some(10)).is_none());
+        assert!(player.advance(ms(200), 1.0, Some(10)).is_some());
+        assert_eq!(player.frame, 3);
+    }
+}
@@ -0,0 +1,169 @@
+use std::{fs, path::Path, sync::Arc};
+
+use color_eyre::{
+    eyre::{ensure, WrapErr},
+    Result,
+};
+use image::{DynamicImage, ImageBuffer};
+use log::warn;
+use moxcms::{
+    ColorProfile, DataColorSpace, Layout, Transform16BitExecutor, Transform8BitExecutor,
+    TransformOptions,
+};
+
+/// Load the ICC profile at `path`, it must describe an RGB color space
+pub fn load_profile(path: &Path) -> Result<ColorProfile> {
+    let data =
+        fs::read(path).wrap_err_with(|| format!("Failed to read the color profile {path:?}"))?;
+    let profile = ColorProfile::new_from_slice(&data)
+        .wrap_err_with(|| format!("Failed to parse the color profile {path:?}"))?;
+    ensure!(
+        profile.color_space == DataColorSpace::Rgb,
+        "The color profile {path:?} is not an RGB profile"
+    );
+    Ok(profile)
+}
+
+/// Convert the colors of a decoded image from the profile embedded in the file to the profile
+/// of the display
+pub struct ColorTransform {
+    source: ColorProfile,
+    target: ColorProfile,
+    /// Created the first time an image with that depth is converted
+    transform_8bit: Option<Arc<Transform8BitExecutor>>,
+    transform_16bit: Option<Arc<Transform16BitExecutor>>,
+}
+
+impl ColorTransform {
+    /// `icc_profile` is the profile embedded in the image and `target` the path to the display
+    /// profile, sRGB is used for the missing ones. Return None if the colors don't need to be
+    /// converted
+    pub fn new(icc_profile: Option<&[u8]>, target: Option<&Path>) -> Result<Option<Self>> {
+        let source = icc_profile.and_then(|icc_profile| {
+            match ColorProfile::new_from_slice(icc_profile) {
+                // The decoders already convert grayscale and CMYK images to RGB
+                Ok(profile) => (profile.color_space == DataColorSpace::Rgb).then_some(profile),
+                Err(err) => {
+                    warn!("Ignoring the invalid ICC profile embedded in the image: {err}");
+                    None
+                }
+            }
+        });
+        if source.is_none() && target.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            source: source.unwrap_or_else(ColorProfile::new_srgb),
+            target: match target {
+                Some(target) => load_profile(target)?,
+                None => ColorProfile::new_srgb(),
+            },
+            transform_8bit: None,
+            transform_16bit: None,
+        }))
+    }
+
+    /// Convert `image` to RGBA in the target profile. Images with more than 8 bits per channel
+    /// are converted with 16 bits of precision, to avoid banding in the gradients
+    pub fn apply(&mut self, image: DynamicImage) -> Result<DynamicImage> {
+        let high_depth = image.color().bytes_per_pixel() / image.color().channel_count() > 1;
+        if high_depth {
+            let image = image.into_rgba16();
+            let transform = match &self.transform_16bit {
+                Some(transform) => transform.clone(),
+                None => self
+                    .transform_16bit
+                    .insert(
+                        self.source
+                            .create_transform_16bit(
+                                Layout::Rgba,
+                                &self.target,
+                                Layout::Rgba,
+                                TransformOptions {
+                                    prefer_fixed_point: false,
+                                    ..TransformOptions::default()
+                                },
+                            )
+                            .wrap_err("Failed to create the color transform")?,
+                    )
+                    .clone(),
+            };
+            let mut converted = ImageBuffer::new(image.width(), image.height());
+            transform
+                .transform(&image, &mut converted)
+                .wrap_err("Failed to convert the colors of the image")?;
+            Ok(DynamicImage::ImageRgba16(converted))
+        } else {
+            let image = image.into_rgba8();
+            let transform = match &self.transform_8bit {
+                Some(transform) => transform.clone(),
+                None => self
+                    .transform_8bit
+                    .insert(
+                        self.source
+                            .create_transform_8bit(
+                                Layout::Rgba,
+                                &self.target,
+                                Layout::Rgba,
+                                TransformOptions::default(),
+                            )
+                            .wrap_err("Failed to create the color transform")?,
+                    )
+                    .clone(),
+            };
+            let mut converted = ImageBuffer::new(image.width(), image.height());
+            transform
+                .transform(&image, &mut converted)
+                .wrap_err("Failed to convert the colors of the image")?;
+            Ok(DynamicImage::ImageRgba8(converted))
+        }
+    }
+}
+
+/// Convert `image` with `transform`, if any
+pub fn convert(
+    transform: Option<&mut ColorTransform>,
+    image: DynamicImage,
+) -> Result<DynamicImage> {
+    match transform {
+        Some(transform) => transform.apply(image),
+        None => Ok(image),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::*;
+
+    #[test]
+    fn test_convert() {
+        let display_p3 = ColorProfile::new_display_p3().encode().unwrap();
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("display-p3.icc");
+        fs::write(&path, &display_p3).unwrap();
+
+        assert!(ColorTransform::new(None, None).unwrap().is_none());
+        // sRGB red is less saturated in Display P3
+        let mut transform = ColorTransform::new(None, Some(&path)).unwrap().unwrap();
+        let red = RgbaImage::from_pixel(1, 1, Rgba([255, 0, 0, 255]));
+        let image = transform.apply(DynamicImage::ImageRgba8(red)).unwrap();
+        let [r, g, b, a] = image.as_rgba8().unwrap().get_pixel(0, 0).0;
+        assert!(r < 245 && g > 20 && b > 20 && a == 255, "{r} {g} {b}");
+
+        // An image with an embedded Display P3 profile, with 16 bits per channel
+        let mut transform = ColorTransform::new(Some(&display_p3), None)
+            .unwrap()
+            .unwrap();
+        let p3_red = ImageBuffer::from_pixel(
+            1,
+            1,
+            Rgba([r as u16 * 257, g as u16 * 257, b as u16 * 257, u16::MAX]),
+        );
+        let image = transform.apply(DynamicImage::ImageRgba16(p3_red)).unwrap();
+        let [r, g, b, _] = image.as_rgba16().unwrap().get_pixel(0, 0).0;
+        assert!(r > 64000 && g < 2000 && b < 2000, "{r} {g} {b}");
+    }
+}
@@ -21,9 +21,13 @@ use serde::Deserialize;
 use smithay_client_toolkit::reexports::calloop::ping::Ping;
 
 use crate::{
+    image_loader::ImageLoader,
     image_picker::ImagePicker,
-    render::Transition,
-    wallpaper_info::{BackgroundMode, Recursive, Sorting, WallpaperInfo},
+    render::{Effect, Transition},
+    wallpaper_info::{
+        Background, BackgroundMode, Color, Gradient, MinResolution, Recursive, SortKey, Sorting,
+        WallpaperInfo,
+    },
 };
 
 use std::os::unix::fs::PermissionsExt;
@@ -33,14 +37,27 @@ use std::os::unix::fs::PermissionsExt;
 pub struct SerializedWallpaperInfo {
     #[serde(default, deserialize_with = "tilde_expansion_deserialize")]
     pub path: Option<PathBuf>,
+    /// Show a solid color instead of the images in path
+    pub color: Option<Color>,
+    /// Show a gradient instead of the images in path
+    pub gradient: Option<Gradient>,
     #[serde(default, with = "humantime_serde")]
     pub duration: Option<Duration>,
+    /// How long before the next change the next image gets decoded
+    #[serde(default, with = "humantime_serde")]
+    pub prefetch: Option<Duration>,
     #[serde(rename = "apply-shadow")]
     pub apply_shadow: Option<bool>,
     pub sorting: Option<Sorting>,
+    /// Key used to order the images with ascending and descending sorting
+    #[serde(rename = "sort-by")]
+    pub sort_by: Option<SortKey>,
     pub mode: Option<BackgroundMode>,
     #[serde(rename = "queue-size")]
     pub queue_size: Option<usize>,
+    /// How many times a favorite image is more likely to be picked by random sorting
+    #[serde(rename = "favorites-weight")]
+    pub favorites_weight: Option<u32>,
     #[serde(rename = "transition-time")]
     pub transition_time: Option<u32>,
 
@@ -51,7 +68,7 @@ pub struct SerializedWallpaperInfo {
     /// See [crate::wallpaper_info::WallpaperInfo]
     #[serde(rename = "initial-transition")]
     pub initial_transition: Option<bool>,
-    pub transition: Option<Transition>,
+    pub transition: Option<TransitionSetting>,
 
     /// Determine the offset for the wallpaper to be drawn into the screen
     /// Must be from 0.0 to 1.0, by default is 0.0 in tile mode and 0.5 in all the others
@@ -66,6 +83,20 @@ pub struct SerializedWallpaperInfo {
     /// Set as true by default
     pub recursive: Option<bool>,
 
+    /// Only pick images with the same orientation of the display
+    #[serde(rename = "match-orientation")]
+    pub match_orientation: Option<bool>,
+    /// Maximum relative difference between the aspect ratio of the images and the display one
+    #[serde(rename = "aspect-tolerance")]
+    pub aspect_tolerance: Option<f32>,
+    /// Minimum resolution of the images, either "native" or WIDTHxHEIGHT
+    #[serde(
+        default,
+        rename = "min-resolution",
+        deserialize_with = "min_resolution_deserialize"
+    )]
+    pub min_resolution: Option<MinResolution>,
+
     // Path to bash script.
     #[serde(default, deserialize_with = "tilde_expansion_deserialize")]
     pub exec: Option<PathBuf>,
@@ -74,12 +105,73 @@ pub struct SerializedWallpaperInfo {
     /// Relative paths are resolved under $XDG_STATE_HOME/wpaperd/wallpapers/.
     #[serde(default, deserialize_with = "tilde_expansion_deserialize")]
     pub symlink: Option<PathBuf>,
+
+    /// Playback speed of animated images
+    #[serde(rename = "animation-speed")]
+    pub animation_speed: Option<f32>,
+    /// Maximum number of frames per second shown for animated images
+    #[serde(rename = "animation-max-fps")]
+    pub animation_max_fps: Option<u32>,
+
+    /// ICC profile of the display, the images are converted to it
+    #[serde(
+        default,
+        rename = "color-profile",
+        deserialize_with = "tilde_expansion_deserialize"
+    )]
+    pub color_profile: Option<PathBuf>,
+    /// Effects applied to the wallpaper when it is drawn
+    pub effects: Option<Vec<Effect>>,
 }
 
 impl SerializedWallpaperInfo {
+    /// Return the background set in this section, if any
+    fn background(&self) -> Result<Option<Background>> {
+        match (self.color, self.gradient) {
+            (Some(_), Some(_)) => Err(eyre!(
+                "{} and {} cannot be both set",
+                "color".bold().italic().blue(),
+                "gradient".bold().italic().blue(),
+            ))
+            .with_suggestion(|| {
+                format!(
+                    "Remove either {} or {}",
+                    "color".bold().italic().blue(),
+                    "gradient".bold().italic().blue(),
+                )
+            }),
+            (Some(color), None) => Ok(Some(Background::Color(color))),
+            (None, Some(gradient)) => Ok(Some(Background::Gradient(gradient))),
+            (None, None) => Ok(None),
+        }
+    }
+
     pub fn apply_and_validate(&self, default: &Self) -> Result<WallpaperInfo> {
+        // color and gradient replace path, the ones in default are ignored when path is set
+        let background = match (self.background()?, default.background()?) {
+            (Some(_), _) if self.path.is_some() => {
+                return Err(eyre!(
+                    "{} cannot be set together with {} or {}",
+                    "path".bold().italic().blue(),
+                    "color".bold().italic().blue(),
+                    "gradient".bold().italic().blue(),
+                ))
+                .with_suggestion(|| {
+                    format!(
+                        "Remove {} to show a color instead of the images",
+                        "path".bold().italic().blue(),
+                    )
+                });
+            }
+            (Some(background), _) => Some(background),
+            (None, Some(background)) if self.path.is_none() => Some(background),
+            (None, _) => None,
+        };
+
         let mut path_inherited = false;
         let path = match (&self.path, &default.path) {
+            // No image is needed
+            _ if background.is_some() => PathBuf::new(),
             (Some(path), None) | (Some(path), Some(_)) => path.to_path_buf(),
             (None, Some(path)) => {
                 path_inherited = true;
@@ -93,7 +185,7 @@ impl SerializedWallpaperInfo {
                 .to_path_buf(),
         };
         // Ensure that a path exists
-        if !path.exists() {
+        if background.is_none() && !path.exists() {
             return Err(eyre!(
                 "Path {} for attribute {}{} must exist",
                 path.to_string_lossy().italic().yellow(),
@@ -115,10 +207,13 @@ impl SerializedWallpaperInfo {
             });
         }
 
+        // Only a single wallpaper is shown, the settings about changing it are not inherited
+        let static_wallpaper = background.is_some() || (path.is_file() && !path_inherited);
+
         let duration = match (&self.duration, &default.duration) {
             // duration is inherited from default, but this section set path to a file, ignore
             // duration
-            (None, Some(_)) if path.is_file() && !path_inherited => None,
+            (None, Some(_)) if static_wallpaper => None,
             (Some(duration), _) | (None, Some(duration)) => Some(*duration),
             (None, None) => None,
         };
@@ -139,14 +234,19 @@ impl SerializedWallpaperInfo {
             }));
         }
 
+        let prefetch = match (&self.prefetch, &default.prefetch) {
+            (Some(prefetch), _) | (None, Some(prefetch)) => *prefetch,
+            (None, None) => ImageLoader::DEFAULT_PREFETCH,
+        };
+
         let sorting = match (&self.sorting, &default.sorting) {
-            (None, Some(_)) if path.is_file() && !path_inherited => None,
+            (None, Some(_)) if static_wallpaper => None,
             (Some(sorting), _) | (None, Some(sorting)) => Some(*sorting),
             (None, None) => None,
         };
 
         let group = match (&self.group, &default.group) {
-            (None, Some(_)) if path.is_file() && !path_inherited => None,
+            (None, Some(_)) if static_wallpaper => None,
             (Some(sorting), _) | (None, Some(sorting)) => Some(*sorting),
             (None, None) => None,
         };
@@ -173,52 +273,119 @@ impl SerializedWallpaperInfo {
             }));
         }
 
-        // If there is no sorting but the group is set
-        let sorting = if group.is_some() && sorting.is_none() {
-            // Assign it the default one, so that we can do the group match below
-            Some(Sorting::default())
-        } else {
-            sorting
-        };
-        let sorting = if let Some(group) = group {
-            match sorting {
-                None | Some(Sorting::Random) => Some(Sorting::GroupedRandom { group }),
-                Some(Sorting::Ascending) | Some(Sorting::Descending) => {
-                    return Err(eyre!(
-                        "{} cannot be combined with {} other than {}",
-                        "group".bold().italic().blue(),
-                        "sorting".bold().italic().blue(),
-                        "random".bold().italic().green(),
-                    ));
-                }
-                Some(Sorting::GroupedRandom { group: _ }) => unreachable!(),
-            }
-        } else {
-            sorting
+        // sort-by is only used by ascending and descending sorting, it is ignored otherwise
+        let sort_by = match (&self.sort_by, &default.sort_by) {
+            (Some(sort_by), _) | (None, Some(sort_by)) => *sort_by,
+            (None, None) => SortKey::default(),
         };
 
         let mode = match (&self.mode, &default.mode) {
             (Some(mode), _) | (None, Some(mode)) => *mode,
             (None, None) => BackgroundMode::default(),
         };
+        // The displays spanning the same image are the ones in the same group
+        if mode == BackgroundMode::Span && group.is_none() {
+            return Err(eyre!(
+                "{} {} requires {} to be set",
+                "mode".bold().italic().blue(),
+                "span".bold().italic().green(),
+                "group".bold().italic().blue(),
+            ))
+            .with_suggestion(|| {
+                format!(
+                    "Assign the same {} to all the displays that should share the image",
+                    "group".bold().italic().blue(),
+                )
+            });
+        }
         let drawn_images_queue_size = match (&self.queue_size, &default.queue_size) {
             (Some(size), _) | (None, Some(size)) => *size,
             (None, None) => ImagePicker::DEFAULT_DRAWN_IMAGES_QUEUE_SIZE,
         };
+        let favorites_weight = match (&self.favorites_weight, &default.favorites_weight) {
+            (Some(weight), _) | (None, Some(weight)) => *weight,
+            (None, None) => 1,
+        };
+        if favorites_weight == 0 {
+            return Err(eyre!(
+                "{} must be a positive number",
+                "favorites-weight".bold().italic().blue(),
+            ))
+            .with_suggestion(|| {
+                format!(
+                    "Set {} to 1 to pick favorites as often as the other images",
+                    "favorites-weight".bold().italic().blue(),
+                )
+            });
+        }
         let initial_transition = match (&self.initial_transition, &default.initial_transition) {
             (Some(initial_transition), _) | (None, Some(initial_transition)) => *initial_transition,
             (None, None) => false,
         };
 
-        let transition = match (&self.transition, &default.transition) {
-            (Some(transition), _) | (None, Some(transition)) => transition.clone(),
-            (None, None) => Transition::Fade {},
+        let transitions = match (&self.transition, &default.transition) {
+            (Some(TransitionSetting::Single(transition)), _)
+            | (None, Some(TransitionSetting::Single(transition))) => {
+                vec![RandomTransition::from(transition.clone())]
+            }
+            (Some(TransitionSetting::Random(transitions)), _)
+            | (None, Some(TransitionSetting::Random(transitions))) => transitions.clone(),
+            (None, None) => vec![RandomTransition::from(Transition::Fade {})],
         };
+        if transitions.is_empty() {
+            return Err(eyre!(
+                "{} must contain at least one transition",
+                "transition.random".bold().italic().blue(),
+            ))
+            .with_suggestion(|| {
+                format!(
+                    "Add some transitions, e.g. {}",
+                    "random = [\"fade\", \"swirl\"]".bold(),
+                )
+            });
+        }
+        // The shader is compiled by each display, only check that it can be read
+        for transition in &transitions {
+            let Transition::Custom { path, .. } = &transition.transition else {
+                continue;
+            };
+            if !path.is_file() {
+                return Err(eyre!(
+                    "Shader {} for attribute {} must be an existing file",
+                    path.to_string_lossy().italic().yellow(),
+                    "transition".bold().italic().blue(),
+                ))
+                .with_suggestion(|| {
+                    format!(
+                        "Set {} to a GLSL file defining {}",
+                        "path".bold().italic().blue(),
+                        "vec4 transition(vec2 uv)".bold(),
+                    )
+                });
+            }
+        }
 
-        let transition_time = match (&self.transition_time, &default.transition_time) {
-            (Some(transition_time), _) | (None, Some(transition_time)) => *transition_time,
-            (None, None) => transition.default_transition_time(),
+        // Each transition has its own default time
+        let transition_time =
+            |transition: &RandomTransition| match (&self.transition_time, &default.transition_time)
+            {
+                (Some(transition_time), _) | (None, Some(transition_time)) => *transition_time,
+                (None, None) => transition.transition.default_transition_time(),
+            };
+        let transition = transitions[0].clone();
+        let random_transitions = if transitions.len() > 1 {
+            transitions
+                .into_iter()
+                .map(|transition| {
+                    let transition_time = transition_time(&transition);
+                    (transition, transition_time)
+                })
+                .collect()
+        } else {
+            Vec::new()
         };
+        let transition_time = transition_time(&transition);
+        let transition = transition.transition;
 
         let offset = match (&self.offset, &default.offset) {
             (Some(offset), _) | (None, Some(offset)) => Some(*offset),
@@ -232,6 +399,28 @@ impl SerializedWallpaperInfo {
             (None, None) => None,
         };
 
+        let match_orientation = match (&self.match_orientation, &default.match_orientation) {
+            (Some(match_orientation), _) | (None, Some(match_orientation)) => *match_orientation,
+            (None, None) => false,
+        };
+
+        let aspect_tolerance = match (&self.aspect_tolerance, &default.aspect_tolerance) {
+            (Some(tolerance), _) | (None, Some(tolerance)) => Some(*tolerance),
+            (None, None) => None,
+        };
+        if let Some(tolerance) = aspect_tolerance {
+            ensure!(
+                tolerance.is_finite() && tolerance >= 0.0,
+                "{} must be a positive number",
+                "aspect-tolerance".bold().italic().blue(),
+            );
+        }
+
+        let min_resolution = match (&self.min_resolution, &default.min_resolution) {
+            (Some(min_resolution), _) | (None, Some(min_resolution)) => Some(*min_resolution),
+            (None, None) => None,
+        };
+
         let exec = match (&self.exec, &default.exec) {
             (Some(exec), _) | (None, Some(exec)) => Some(exec.to_path_buf()),
             (None, None) => None,
@@ -242,6 +431,61 @@ impl SerializedWallpaperInfo {
             (None, None) => None,
         };
 
+        let animation_speed = match (&self.animation_speed, &default.animation_speed) {
+            (Some(speed), _) | (None, Some(speed)) => *speed,
+            (None, None) => 1.0,
+        };
+        ensure!(
+            animation_speed.is_finite() && animation_speed > 0.0,
+            "{} must be a positive number",
+            "animation-speed".bold().italic().blue(),
+        );
+
+        let animation_max_fps = match (&self.animation_max_fps, &default.animation_max_fps) {
+            (Some(max_fps), _) | (None, Some(max_fps)) => Some(*max_fps),
+            (None, None) => None,
+        };
+        if animation_max_fps == Some(0) {
+            return Err(eyre!(
+                "{} must be a positive number",
+                "animation-max-fps".bold().italic().blue(),
+            ))
+            .with_suggestion(|| {
+                format!(
+                    "Remove {} to show the animations at their own frame rate",
+                    "animation-max-fps".bold().italic().blue(),
+                )
+            });
+        }
+
+        let color_profile = match (&self.color_profile, &default.color_profile) {
+            (Some(profile), _) | (None, Some(profile)) => Some(profile.to_path_buf()),
+            (None, None) => None,
+        };
+        if let Some(color_profile) = &color_profile {
+            ensure!(
+                color_profile.exists(),
+                "Color profile {} must exist",
+                color_profile.to_string_lossy().italic().yellow()
+            );
+            crate::color::load_profile(color_profile)
+                .wrap_err_with(|| format!("Invalid {}", "color-profile".bold().italic().blue()))?;
+        }
+
+        let effects = match (&self.effects, &default.effects) {
+            (Some(effects), _) | (None, Some(effects)) => effects.clone(),
+            (None, None) => Vec::new(),
+        };
+        for effect in &effects {
+            if let Err(reason) = effect.check() {
+                return Err(eyre!(
+                    "Invalid effect {} in {}: {reason}",
+                    format!("{effect:?}").italic().yellow(),
+                    "effects".bold().italic().blue(),
+                ));
+            }
+        }
+
         if let Some(exec_path) = &exec {
             ensure!(
                 exec_path.exists(),
@@ -262,18 +506,31 @@ impl SerializedWallpaperInfo {
 
         Ok(WallpaperInfo {
             path,
+            background,
             duration,
+            prefetch,
             apply_shadow: false,
             sorting,
+            group,
+            sort_by,
             mode,
             drawn_images_queue_size,
+            favorites_weight,
             transition_time,
             initial_transition,
             transition,
+            random_transitions,
             offset,
             recursive,
+            match_orientation,
+            aspect_tolerance,
+            min_resolution,
             exec,
             symlink,
+            animation_speed,
+            animation_max_fps,
+            color_profile,
+            effects,
         })
     }
 }
@@ -338,10 +595,10 @@ impl Config {
                     .map(|res| (name, res))
                     .ok()
             })
-            .filter(|(_, info)| matches!(info.sorting, Some(Sorting::GroupedRandom { .. })))
+            .filter(|(_, info)| info.group.is_some())
             .collect::<Vec<_>>();
 
-        // Check if all the groups share the same path
+        // Check if all the groups share the same path and sorting
         // This check is only useful when there is more than one display
         // We display a warning related to the config issue, but we are not fixing anything here
         // The path that will be used is probably not what the user expect, but proving an "expected"
@@ -356,18 +613,16 @@ impl Config {
                         continue;
                     }
                     let y = groups.get(j).unwrap();
-                    if !(x.1.sorting.is_none()
-                        || x.1.sorting != y.1.sorting
-                        || x.1.path == y.1.path)
+                    if x.1.group == y.1.group
+                        && (x.1.path != y.1.path
+                            || x.1.sorting != y.1.sorting
+                            || x.1.sort_by != y.1.sort_by)
                     {
                         warn!(
-                            "Displays {} and {} are assigned to group {} but have different paths",
+                            "Displays {} and {} are assigned to group {} but have different paths or sorting",
                             x.0,
                             y.0,
-                            match x.1.sorting {
-                                Some(Sorting::GroupedRandom { group }) => group,
-                                _ => unreachable!(),
-                            }
+                            x.1.group.unwrap(),
                         );
                         errored_list.push(j);
                     }
@@ -507,6 +762,173 @@ impl PartialEq for Config {
     }
 }
 
+/// The value of the attribute transition: either a single transition or a list of transitions
+/// to pick from randomly, written as `{ random = [...] }`
+#[derive(Clone, Debug, PartialEq)]
+pub enum TransitionSetting {
+    Single(Transition),
+    Random(Vec<RandomTransition>),
+}
+
+/// A transition listed in `transition.random`. Its numeric settings can be written as
+/// `[min, max]`, a new value in the range is picked each time the transition is used
+#[derive(Clone, Debug, PartialEq)]
+pub struct RandomTransition {
+    /// The transition with the minimum of each range
+    pub transition: Transition,
+    /// The name of the transition and its settings, as written in the configuration
+    name: String,
+    settings: toml::Table,
+    ranges: Vec<(String, SettingRange)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SettingRange {
+    Int(i64, i64),
+    Float(f64, f64),
+}
+
+impl RandomTransition {
+    /// Parse `{ name = { settings } }`. A setting is a range if it's an array of two numbers and
+    /// the transition accepts a single number for it, unlike `direction = [0, 1]`
+    fn new(name: String, mut settings: toml::Table) -> Result<Self, String> {
+        let parse = |settings: &toml::Table| {
+            toml::Value::Table(toml::Table::from_iter([(
+                name.clone(),
+                toml::Value::Table(settings.clone()),
+            )]))
+            .try_into::<Transition>()
+        };
+        let mut ranges = Vec::new();
+        for (key, value) in settings.clone() {
+            let Some((min, max)) = value.as_array().and_then(|range| match range.as_slice() {
+                [min, max] => Some((value_as_f64(min)?, value_as_f64(max)?)),
+                _ => None,
+            }) else {
+                continue;
+            };
+            // All the settings are optional, check this one alone
+            let accepts =
+                |value: toml::Value| parse(&toml::Table::from_iter([(key.clone(), value)])).is_ok();
+            let range = if accepts(toml::Value::Float(min + 0.5)) {
+                SettingRange::Float(min, max)
+            } else if accepts(toml::Value::Integer(min as i64)) {
+                SettingRange::Int(min.round() as i64, max.round() as i64)
+            } else {
+                continue;
+            };
+            if min > max {
+                return Err(format!("the range of {key} must be written as [min, max]"));
+            }
+            settings.insert(key.clone(), range.min());
+            ranges.push((key, range));
+        }
+        let transition = parse(&settings).map_err(|err| err.to_string())?;
+        Ok(Self {
+            transition,
+            name,
+            settings,
+            ranges,
+        })
+    }
+
+    /// Return the transition with a random value for each setting written as a range
+    pub fn sample(&self) -> Transition {
+        if self.ranges.is_empty() {
+            return self.transition.clone();
+        }
+        let mut settings = self.settings.clone();
+        for (key, range) in &self.ranges {
+            settings.insert(key.clone(), range.sample());
+        }
+        toml::Value::Table(toml::Table::from_iter([(
+            self.name.clone(),
+            toml::Value::Table(settings),
+        )]))
+        .try_into()
+        .unwrap_or_else(|_| self.transition.clone())
+    }
+}
+
+impl From<Transition> for RandomTransition {
+    fn from(transition: Transition) -> Self {
+        Self {
+            transition,
+            name: String::new(),
+            settings: toml::Table::new(),
+            ranges: Vec::new(),
+        }
+    }
+}
+
+impl SettingRange {
+    fn min(self) -> toml::Value {
+        match self {
+            SettingRange::Int(min, _) => toml::Value::Integer(min),
+            SettingRange::Float(min, _) => toml::Value::Float(min),
+        }
+    }
+
+    fn sample(self) -> toml::Value {
+        match self {
+            SettingRange::Int(min, max) => toml::Value::Integer(fastrand::i64(min..=max)),
+            SettingRange::Float(min, max) => {
+                toml::Value::Float(min + fastrand::f64() * (max - min))
+            }
+        }
+    }
+}
+
+fn value_as_f64(value: &toml::Value) -> Option<f64> {
+    match value {
+        toml::Value::Integer(value) => Some(*value as f64),
+        toml::Value::Float(value) => Some(*value),
+        _ => None,
+    }
+}
+
+impl<'de> Deserialize<'de> for TransitionSetting {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        match toml::Value::deserialize(deserializer)? {
+            toml::Value::Table(mut table) if table.len() == 1 && table.contains_key("random") => {
+                let Some(toml::Value::Array(transitions)) = table.remove("random") else {
+                    return Err(D::Error::custom("random must be a list of transitions"));
+                };
+                transitions
+                    .into_iter()
+                    .map(|transition| match transition {
+                        // The transitions without any setting can be written by name
+                        toml::Value::String(name) => {
+                            RandomTransition::new(name, toml::Table::new())
+                        }
+                        toml::Value::Table(table) if table.len() == 1 => {
+                            let (name, settings) = table.into_iter().next().unwrap();
+                            match settings {
+                                toml::Value::Table(settings) => {
+                                    RandomTransition::new(name, settings)
+                                }
+                                _ => Err(format!("the settings of {name} must be a table")),
+                            }
+                        }
+                        _ => Err("a transition must be a name or a table".to_string()),
+                    })
+                    .map(|transition| transition.map_err(D::Error::custom))
+                    .collect::<Result<_, _>>()
+                    .map(Self::Random)
+            }
+            transition => transition
+                .try_into()
+                .map(Self::Single)
+                .map_err(D::Error::custom),
+        }
+    }
+}
+
 pub fn tilde_expansion_deserialize<'de, D>(deserializer: D) -> Result<Option<PathBuf>, D::Error>
 where
     D: serde::Deserializer<'de>,
@@ -520,6 +942,18 @@ where
     ))
 }
 
+pub fn min_resolution_deserialize<'de, D>(
+    deserializer: D,
+) -> Result<Option<MinResolution>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
 /// Return true if `path` contains any template variable (`%PORT%` or `%NAME%`).
 pub fn has_template_vars(path: &Path) -> bool {
     let s = path.to_string_lossy();
@@ -562,6 +996,224 @@ fn is_port_name(s: &str) -> bool {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::render::transition::UniformValue;
+
+    #[test]
+    fn test_min_resolution() {
+        let info: SerializedWallpaperInfo = toml::from_str("min-resolution = \"native\"").unwrap();
+        assert_eq!(info.min_resolution, Some(MinResolution::Native));
+        let info: SerializedWallpaperInfo =
+            toml::from_str("min-resolution = \"3840x2160\"").unwrap();
+        assert_eq!(
+            info.min_resolution,
+            Some(MinResolution::Size {
+                width: 3840,
+                height: 2160
+            })
+        );
+        assert!(toml::from_str::<SerializedWallpaperInfo>("min-resolution = \"4k\"").is_err());
+    }
+
+    #[test]
+    fn test_group_with_ordered_sorting() {
+        let info = SerializedWallpaperInfo {
+            path: Some(std::env::temp_dir()),
+            sorting: Some(Sorting::Ascending),
+            group: Some(1),
+            ..Default::default()
+        };
+        let info = info
+            .apply_and_validate(&SerializedWallpaperInfo::default())
+            .unwrap();
+        assert_eq!(info.sorting, Some(Sorting::Ascending));
+        assert_eq!(info.group, Some(1));
+    }
+
+    #[test]
+    fn test_background() {
+        let info: SerializedWallpaperInfo =
+            toml::from_str("gradient = { from = \"#1e1e2e\", to = \"11111B\", angle = 90 }")
+                .unwrap();
+        let default = SerializedWallpaperInfo {
+            path: Some(std::env::temp_dir()),
+            duration: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let info = info.apply_and_validate(&default).unwrap();
+        assert_eq!(
+            info.background,
+            Some(Background::Gradient(Gradient {
+                from: Color([0x1e, 0x1e, 0x2e]),
+                to: Color([0x11, 0x11, 0x1b]),
+                angle: 90.0,
+            }))
+        );
+        // The duration of default doesn't apply to a color
+        assert_eq!(info.duration, None);
+
+        assert!(toml::from_str::<SerializedWallpaperInfo>("color = \"#1e1e2\"").is_err());
+        let info = SerializedWallpaperInfo {
+            path: Some(std::env::temp_dir()),
+            color: Some(Color([0, 0, 0])),
+            ..Default::default()
+        };
+        assert!(info.apply_and_validate(&default).is_err());
+    }
+
+    #[test]
+    fn test_custom_transition() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let shader = dir.join("wipe.glsl");
+        fs::write(
+            &shader,
+            "vec4 transition(vec2 uv) { return getToColor(uv); }",
+        )
+        .unwrap();
+        let default = SerializedWallpaperInfo {
+            path: Some(dir.to_path_buf()),
+            ..Default::default()
+        };
+
+        let info: SerializedWallpaperInfo = toml::from_str(&format!(
+            "transition.custom = {{ path = {shader:?}, uniforms = {{ steps = 3, speed = 2.0, \
+             reverse = true, direction = [1, 0] }} }}"
+        ))
+        .unwrap();
+        let info = info.apply_and_validate(&default).unwrap();
+        let Transition::Custom { path, uniforms } = info.transition else {
+            panic!("unexpected transition {:?}", info.transition);
+        };
+        assert_eq!(path, shader);
+        assert_eq!(
+            uniforms.into_iter().collect::<Vec<_>>(),
+            [
+                ("direction".to_string(), UniformValue::Vec2([1.0, 0.0])),
+                ("reverse".to_string(), UniformValue::Bool(true)),
+                ("speed".to_string(), UniformValue::Float(2.0)),
+                ("steps".to_string(), UniformValue::Int(3)),
+            ]
+        );
+        assert_eq!(info.transition_time, 1000);
+
+        let info: SerializedWallpaperInfo = toml::from_str(&format!(
+            "transition.custom.path = {:?}",
+            dir.join("missing.glsl")
+        ))
+        .unwrap();
+        assert!(info.apply_and_validate(&default).is_err());
+    }
+
+    #[test]
+    fn test_random_transition() {
+        let default = SerializedWallpaperInfo {
+            path: Some(std::env::temp_dir()),
+            transition_time: Some(500),
+            ..Default::default()
+        };
+        let info: SerializedWallpaperInfo =
+            toml::from_str("transition = { random = [\"fade\", { doom = { bars = 10 } }] }")
+                .unwrap();
+        let info = info.apply_and_validate(&default).unwrap();
+        assert_eq!(info.transition, Transition::Fade {});
+        assert_eq!(info.random_transitions.len(), 2);
+        assert_eq!(info.random_transitions[1].1, 500);
+
+        // Each transition uses its own default time
+        let info: SerializedWallpaperInfo =
+            toml::from_str("transition.random = [\"fade\", \"swirl\"]").unwrap();
+        let info = info
+            .apply_and_validate(&SerializedWallpaperInfo {
+                path: Some(std::env::temp_dir()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(
+            info.random_transitions
+                .iter()
+                .map(|(transition, time)| (transition.sample(), *time))
+                .collect::<Vec<_>>(),
+            [(Transition::Fade {}, 300), (Transition::Swirl {}, 1500)]
+        );
+
+        // A new value is picked in each range, vectors are not ranges
+        let info: SerializedWallpaperInfo = toml::from_str(
+            "transition.random = [{ doom = { bars = [10, 20], amplitude = [1, 2.5] } }, \
+             { directional = { direction = [1, 0] } }]",
+        )
+        .unwrap();
+        let info = info.apply_and_validate(&default).unwrap();
+        for _ in 0..20 {
+            let Transition::Doom {
+                bars: Some(bars),
+                amplitude: Some(amplitude),
+                ..
+            } = info.random_transitions[0].0.sample()
+            else {
+                panic!("unexpected transition {:?}", info.random_transitions[0]);
+            };
+            assert!((10..=20).contains(&bars));
+            assert!((1.0..=2.5).contains(&amplitude));
+        }
+        assert_eq!(
+            info.random_transitions[1].0.sample(),
+            Transition::Directional {
+                direction: Some([1.0, 0.0])
+            }
+        );
+        assert!(toml::from_str::<SerializedWallpaperInfo>(
+            "transition.random = [{ doom = { bars = [20, 10] } }]"
+        )
+        .is_err());
+
+        // A single transition is still a table
+        let info: SerializedWallpaperInfo = toml::from_str("transition.swirl = {}").unwrap();
+        let info = info.apply_and_validate(&default).unwrap();
+        assert_eq!(info.transition, Transition::Swirl {});
+        assert!(info.random_transitions.is_empty());
+
+        assert!(
+            toml::from_str::<SerializedWallpaperInfo>("transition.random = [\"foo\"]").is_err()
+        );
+        let info: SerializedWallpaperInfo = toml::from_str("transition.random = []").unwrap();
+        assert!(info.apply_and_validate(&default).is_err());
+    }
+
+    #[test]
+    fn test_effects() {
+        let default = SerializedWallpaperInfo {
+            path: Some(std::env::temp_dir()),
+            effects: Some(vec![Effect::Grayscale]),
+            ..Default::default()
+        };
+        let info: SerializedWallpaperInfo = toml::from_str(
+            "effects = [{ blur = 8 }, { dim = 0.3 }, \"grayscale\", \
+             { tint = { color = \"#ff8800\", amount = 0.2 } }]",
+        )
+        .unwrap();
+        let info = info.apply_and_validate(&default).unwrap();
+        assert_eq!(
+            info.effects,
+            [
+                Effect::Blur(8.0),
+                Effect::Dim(0.3),
+                Effect::Grayscale,
+                Effect::Tint {
+                    color: Color([0xff, 0x88, 0x00]),
+                    amount: 0.2
+                }
+            ]
+        );
+        // Inherited from default
+        let info = SerializedWallpaperInfo::default()
+            .apply_and_validate(&default)
+            .unwrap();
+        assert_eq!(info.effects, [Effect::Grayscale]);
+
+        let info: SerializedWallpaperInfo = toml::from_str("effects = [{ dim = 2.0 }]").unwrap();
+        assert!(info.apply_and_validate(&default).is_err());
+        assert!(toml::from_str::<SerializedWallpaperInfo>("effects = [\"sharpen\"]").is_err());
+    }
 
     #[test]
     fn test_clean_monitor_description() {
@@ -3,6 +3,18 @@ use smithay_client_toolkit::{
     shell::wlr_layer::LayerSurfaceConfigure,
 };
 
+/// Area covered by a display inside the canvas of the displays spanning the same wallpaper.
+/// All the values are relative to the size of the canvas.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpanArea {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    /// Aspect ratio of the whole canvas
+    pub canvas_ratio: f32,
+}
+
 #[derive(Debug)]
 pub struct DisplayInfo {
     pub name: String,
@@ -11,6 +23,8 @@ pub struct DisplayInfo {
     pub height: i32,
     pub scale: i32,
     pub transform: Transform,
+    /// Set when the display is spanning a wallpaper with other displays
+    pub span_area: Option<SpanArea>,
 }
 
 impl DisplayInfo {
@@ -22,6 +36,7 @@ impl DisplayInfo {
             height: 0,
             scale: info.scale_factor,
             transform: info.transform,
+            span_area: None,
         }
     }
 
@@ -62,7 +77,6 @@ impl DisplayInfo {
     }
 
     #[inline]
-    #[allow(unused)]
     pub fn ratio(&self) -> f32 {
         self.adjusted_width() as f32 / self.adjusted_height() as f32
     }
@@ -103,6 +117,15 @@ impl DisplayInfo {
         }
     }
 
+    pub fn change_span_area(&mut self, span_area: Option<SpanArea>) -> bool {
+        if self.span_area != span_area {
+            self.span_area = span_area;
+            true
+        } else {
+            false
+        }
+    }
+
     pub fn is_configured(&self) -> bool {
         self.width != 0 && self.height != 0
     }
@@ -1,99 +1,500 @@
 use std::{
+    cell::RefCell,
+    cmp::Ordering as CmpOrdering,
+    collections::{HashMap, HashSet},
+    iter::Peekable,
     path::{Path, PathBuf},
+    str::Chars,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
     },
+    time::{Duration, Instant, SystemTime},
 };
 
 use color_eyre::eyre::{eyre, Result, WrapErr};
-use hotwatch::Hotwatch;
+use hotwatch::{notify::event::ModifyKind, EventKind, Hotwatch};
 use log::error;
-use smithay_client_toolkit::reexports::calloop::{self, ping::Ping, LoopHandle};
+use smithay_client_toolkit::reexports::calloop::{
+    self,
+    ping::Ping,
+    timer::{TimeoutAction, Timer},
+    LoopHandle,
+};
 use walkdir::WalkDir;
 
-use crate::{wallpaper_info::Recursive, wpaperd::Wpaperd};
+use crate::{
+    image_format,
+    image_lists::ImageLists,
+    media_index::{DecodeStatus, FileKey, MediaEntry, MediaIndex},
+    wallpaper_info::{Recursive, SortKey},
+    wpaperd::Wpaperd,
+};
+
+/// Paths notified by the watcher that have not been applied to a [Filelist] yet
+#[derive(Debug, Default)]
+struct PendingChanges {
+    paths: HashSet<PathBuf>,
+    /// When the last change has been received
+    last_change: Option<Instant>,
+}
 
 #[derive(Debug)]
 struct Filelist {
     path: PathBuf,
     recursive: Recursive,
+    /// Files sorted by name
     filelist: Arc<Vec<PathBuf>>,
+    /// The filelist sorted by the other keys, computed the first time they are requested from
+    /// the keys stored in the [MediaIndex]
+    sorted: RefCell<HashMap<SortKey, Arc<Vec<PathBuf>>>>,
+    /// The whole directory needs to be read again
     outdated: Arc<AtomicBool>,
+    /// The directory is being read in the background
+    scanning: bool,
+    changes: Arc<Mutex<PendingChanges>>,
+    /// The pending changes are being read in the background
+    applying_changes: bool,
+}
+
+/// The images found by reading a directory in the background
+struct Scan {
+    path: PathBuf,
+    recursive: Recursive,
+    files: Vec<PathBuf>,
+}
+
+/// The images found by reading the paths changed inside a directory in the background
+struct ChangesScan {
+    path: PathBuf,
+    recursive: Recursive,
+    /// The paths notified by the watcher
+    changed: HashSet<PathBuf>,
+    /// The images found in the paths that still exist
+    found: Vec<PathBuf>,
+}
+
+/// Return the images in `path`, which is either `root` or a file or directory inside it
+fn walk<'a>(
+    root: &'a Path,
+    recursive: Recursive,
+    path: &Path,
+    index: &'a HashMap<PathBuf, MediaEntry>,
+) -> impl Iterator<Item = PathBuf> + 'a {
+    WalkDir::new(path)
+        .max_depth(if recursive == Recursive::Off {
+            1
+        } else {
+            usize::MAX
+        })
+        .follow_links(true)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter(move |e| recursive == Recursive::On || e.path().parent() == Some(root))
+        // Check the content last, it needs to open the file. The images in the index that
+        // have not been modified since don't need to be checked again
+        .filter(|e| {
+            index.get(e.path()).is_some_and(|entry| {
+                e.metadata()
+                    .is_ok_and(|metadata| FileKey::from(&metadata) == entry.key)
+            }) || image_format::is_supported(e.path())
+        })
+        .map(|e| e.into_path())
 }
 
 impl Filelist {
+    /// The filelist is empty until it gets populated by [FilelistCache::update_cache]
     fn new(path: &Path, recursive: Recursive) -> Self {
-        let mut res = Self {
+        Self {
             path: path.to_path_buf(),
             recursive,
             filelist: Arc::new(Vec::new()),
+            sorted: RefCell::new(HashMap::new()),
             outdated: Arc::new(AtomicBool::new(true)),
-        };
-        res.populate();
-        res
+            scanning: false,
+            changes: Arc::new(Mutex::new(PendingChanges::default())),
+            applying_changes: false,
+        }
     }
 
-    fn populate(&mut self) {
+    /// Replace the files with the ones found by a [Scan]
+    fn publish(&mut self, files: Vec<PathBuf>, lists: &ImageLists) {
         self.filelist = Arc::new(
-            WalkDir::new(&self.path)
-                .max_depth(if self.recursive == Recursive::Off {
-                    1
-                } else {
-                    usize::MAX
-                })
-                .follow_links(true)
-                .sort_by_file_name()
+            files
                 .into_iter()
-                .filter_map(|e| e.ok())
-                .filter(|e| {
-                    if let Some(guess) = new_mime_guess::from_path(e.path()).first() {
-                        guess.type_() == "image"
-                    } else {
-                        false
-                    }
-                })
-                .map(|e| e.path().to_path_buf())
+                .filter(|path| !lists.is_excluded(path))
                 .collect(),
         );
-        self.outdated.store(false, Ordering::Relaxed);
+        self.sorted.borrow_mut().clear();
+        self.scanning = false;
+    }
+
+    /// Take the pending changes, if no change has been received for `debounce`.
+    /// Otherwise return when they can be taken.
+    fn take_changes(&self, debounce: Duration) -> Result<HashSet<PathBuf>, Option<Instant>> {
+        let mut changes = self.changes.lock().unwrap();
+        let Some(last_change) = changes.last_change else {
+            return Err(None);
+        };
+        if last_change.elapsed() < debounce {
+            return Err(Some(last_change + debounce));
+        }
+        changes.last_change = None;
+        Ok(std::mem::take(&mut changes.paths))
+    }
+
+    /// Update the files affected by the changes in `paths` without reading the whole directory,
+    /// `found` are the images returned by [find_changes]. Return the images that have been added
+    /// or modified.
+    fn apply_changes(
+        &mut self,
+        paths: &HashSet<PathBuf>,
+        found: Vec<PathBuf>,
+        lists: &ImageLists,
+    ) -> Vec<PathBuf> {
+        let paths: HashSet<&Path> = paths
+            .iter()
+            .filter(|path| path.starts_with(&self.path))
+            .map(PathBuf::as_path)
+            .collect();
+        if paths.is_empty() {
+            return Vec::new();
+        }
+
+        // Remove the files that changed, or that are inside a directory that changed,
+        // then add back the ones that still exist
+        let mut files: Vec<PathBuf> = self
+            .filelist
+            .iter()
+            .filter(|file| !file.ancestors().any(|ancestor| paths.contains(ancestor)))
+            .cloned()
+            .collect();
+        let changed: Vec<PathBuf> = found
+            .into_iter()
+            .filter(|path| !lists.is_excluded(path))
+            .collect();
+        files.extend(changed.iter().cloned());
+        // Paths are ordered component by component, like the files returned by WalkDir
+        files.sort_unstable();
+        files.dedup();
+
+        if files != *self.filelist {
+            self.filelist = Arc::new(files);
+            self.sorted.borrow_mut().clear();
+        }
+        changed
+    }
+
+    fn remove(&mut self, path: &Path) {
+        if self.filelist.iter().any(|file| file == path) {
+            self.filelist = Arc::new(
+                self.filelist
+                    .iter()
+                    .filter(|file| *file != path)
+                    .cloned()
+                    .collect(),
+            );
+            self.sorted.borrow_mut().clear();
+        }
+    }
+
+    fn get(&self, sort_key: SortKey, index: &HashMap<PathBuf, MediaEntry>) -> Arc<Vec<PathBuf>> {
+        match sort_key {
+            SortKey::Name => self.filelist.clone(),
+            _ => self
+                .sorted
+                .borrow_mut()
+                .entry(sort_key)
+                .or_insert_with(|| Arc::new(sort_files(&self.filelist, sort_key, index)))
+                .clone(),
+        }
+    }
+
+    /// Sort the files again the next time, new modification times or EXIF dates are available
+    fn index_updated(&self) {
+        self.sorted
+            .borrow_mut()
+            .retain(|sort_key, _| *sort_key == SortKey::Natural);
     }
 }
 
+/// Return the images in the `paths` that changed inside `root` and still exist. It reads the
+/// directories and the files, so it's called in the background
+fn find_changes(
+    root: &Path,
+    recursive: Recursive,
+    paths: &HashSet<PathBuf>,
+    index: &HashMap<PathBuf, MediaEntry>,
+) -> Vec<PathBuf> {
+    paths
+        .iter()
+        .filter(|path| path.starts_with(root) && path.exists())
+        .flat_map(|path| walk(root, recursive, path, index))
+        .collect()
+}
+
+/// Width and height of an image, after applying its EXIF orientation
+pub type Dimensions = (u32, u32);
+
+/// Return `files` (sorted by name) ordered by `sort_key`. The modification times and the EXIF
+/// dates are taken from `index`, the files that have not been probed yet come last
+fn sort_files(
+    files: &[PathBuf],
+    sort_key: SortKey,
+    index: &HashMap<PathBuf, MediaEntry>,
+) -> Vec<PathBuf> {
+    let mut files = files.to_vec();
+    let entry = |file: &PathBuf| index.get(file);
+    // Use stable sorts, so that files with the same key stay sorted by name
+    match sort_key {
+        SortKey::Name => {}
+        SortKey::Natural => {
+            files.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+        }
+        SortKey::Mtime => {
+            files.sort_by_key(|file| {
+                let mtime = entry(file).and_then(|entry| entry.key.mtime);
+                (mtime.is_none(), mtime)
+            });
+        }
+        SortKey::Exif => {
+            // Images without a date come last
+            files.sort_by_key(|file| {
+                let entry = entry(file);
+                let exif_date = entry.and_then(|entry| entry.exif_date);
+                let mtime = entry.and_then(|entry| entry.key.mtime);
+                (exif_date.is_none(), exif_date, mtime.is_none(), mtime)
+            });
+        }
+    }
+    files
+}
+
+pub fn mtime(path: &Path) -> Option<SystemTime> {
+    path.metadata().and_then(|m| m.modified()).ok()
+}
+
+/// Compare two strings, treating sequences of digits as numbers
+fn natural_cmp(a: &str, b: &str) -> CmpOrdering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        let ordering = match (a.peek(), b.peek()) {
+            (None, None) => return CmpOrdering::Equal,
+            (None, Some(_)) => return CmpOrdering::Less,
+            (Some(_), None) => return CmpOrdering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_number(&mut a);
+                let y = take_number(&mut b);
+                let (x_trimmed, y_trimmed) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                // A longer number is bigger, otherwise compare digit by digit.
+                // On ties, the number with fewer leading zeros comes first
+                x_trimmed
+                    .len()
+                    .cmp(&y_trimmed.len())
+                    .then_with(|| x_trimmed.cmp(y_trimmed))
+                    .then_with(|| x.len().cmp(&y.len()))
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.cmp(y);
+                a.next();
+                b.next();
+                ordering
+            }
+        };
+        if ordering != CmpOrdering::Equal {
+            return ordering;
+        }
+    }
+}
+
+fn take_number(chars: &mut Peekable<Chars>) -> String {
+    let mut number = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        number.push(c);
+    }
+    number
+}
+
 pub struct FilelistCache {
     cache: Vec<Filelist>,
+    event_loop_handle: LoopHandle<'static, Wpaperd>,
+    /// A timer to apply the pending changes has been added to the event loop
+    changes_scheduled: bool,
+    lists: ImageLists,
+    /// Metadata of the images, read in the background and kept across restarts
+    index: MediaIndex,
+    /// A timer to save the index has been added to the event loop
+    index_save_scheduled: bool,
+    /// Incremented every time new headers are available
+    headers_generation: u64,
+    headers_sender: Sender<Vec<(PathBuf, MediaEntry)>>,
+    headers_receiver: Receiver<Vec<(PathBuf, MediaEntry)>>,
+    scan_sender: Sender<Scan>,
+    scan_receiver: Receiver<Scan>,
+    changes_sender: Sender<ChangesScan>,
+    changes_receiver: Receiver<ChangesScan>,
+    ping: Ping,
 }
 
 impl FilelistCache {
+    /// Wait for this long without changes in a directory before updating its filelist
+    const CHANGES_DEBOUNCE: Duration = Duration::from_secs(1);
+    /// Read all the directories again periodically, in case the watcher missed some changes
+    const RESCAN_INTERVAL: Duration = Duration::from_secs(60 * 60);
+    /// Group the changes to the index, instead of writing it after each one
+    const INDEX_SAVE_DELAY: Duration = Duration::from_secs(30);
+
     pub fn new(
         paths: Vec<(PathBuf, Recursive)>,
+        mut lists: ImageLists,
+        index: MediaIndex,
         hotwatch: &mut Hotwatch,
-        event_loop_handle: LoopHandle<Wpaperd>,
+        event_loop_handle: LoopHandle<'static, Wpaperd>,
     ) -> Result<(Ping, Self)> {
         let (ping, ping_source) =
             calloop::ping::make_ping().wrap_err("Failed to initialize a calloop::ping::Ping")?;
 
-        let mut filelist_cache = Self { cache: Vec::new() };
+        // Skip the images that failed to decode during the previous runs
+        for (path, entry) in index.entries().iter() {
+            if entry.status == DecodeStatus::Failed {
+                lists.failed.insert(path.clone(), entry.key.mtime);
+            }
+        }
+
+        let (headers_sender, headers_receiver) = mpsc::channel();
+        let (scan_sender, scan_receiver) = mpsc::channel();
+        let (changes_sender, changes_receiver) = mpsc::channel();
+        let mut filelist_cache = Self {
+            cache: Vec::new(),
+            event_loop_handle: event_loop_handle.clone(),
+            changes_scheduled: false,
+            lists,
+            index,
+            index_save_scheduled: false,
+            headers_generation: 0,
+            headers_sender,
+            headers_receiver,
+            scan_sender,
+            scan_receiver,
+            changes_sender,
+            changes_receiver,
+            ping: ping.clone(),
+        };
         filelist_cache.update_paths(paths, hotwatch, ping.clone());
         event_loop_handle
             .insert_source(ping_source, move |_, _, wpaperd| {
-                wpaperd.filelist_cache.borrow_mut().update_cache();
+                let updated = wpaperd.filelist_cache.borrow_mut().update_cache();
+                if updated {
+                    wpaperd.load_missing_wallpapers();
+                }
             })
             .map_err(|e| eyre!("{e:?}"))
             .wrap_err("Failed to insert the filelist cache watcher in the event loop")?;
+        event_loop_handle
+            .insert_source(
+                Timer::from_duration(Self::RESCAN_INTERVAL),
+                |_, _, wpaperd| {
+                    wpaperd.filelist_cache.borrow_mut().rescan();
+                    TimeoutAction::ToDuration(Self::RESCAN_INTERVAL)
+                },
+            )
+            .map_err(|e| eyre!("{e:?}"))
+            .wrap_err("Failed to insert the filelist rescan timer in the event loop")?;
 
         Ok((ping, filelist_cache))
     }
 
-    pub fn get(&self, path: &Path, recursive: Recursive) -> Arc<Vec<PathBuf>> {
+    pub fn get(&self, path: &Path, recursive: Recursive, sort_key: SortKey) -> Arc<Vec<PathBuf>> {
         debug_assert!(path.is_dir());
         self.cache
             .iter()
             .find(|filelist| filelist.path == path && filelist.recursive == recursive)
-            .map(|filelist| filelist.filelist.clone())
+            .map(|filelist| filelist.get(sort_key, &self.index.entries()))
             .unwrap_or_default()
     }
 
+    /// Return true if the directory is being read in the background
+    pub fn is_scanning(&self, path: &Path, recursive: Recursive) -> bool {
+        self.cache.iter().any(|filelist| {
+            filelist.path == path && filelist.recursive == recursive && filelist.scanning
+        })
+    }
+
+    /// Return the dimensions of the image, if its header has been read
+    pub fn dimensions(&self, path: &Path) -> Option<Dimensions> {
+        self.index.get(path).and_then(|entry| entry.dimensions)
+    }
+
+    /// Return a number that changes every time new dimensions are available
+    #[inline]
+    pub fn dimensions_generation(&self) -> u64 {
+        self.headers_generation
+    }
+
+    #[inline]
+    pub fn lists(&self) -> &ImageLists {
+        &self.lists
+    }
+
+    pub fn favorite(&mut self, path: PathBuf) -> Result<bool> {
+        self.lists.favorites.insert(path)
+    }
+
+    pub fn unfavorite(&mut self, path: &Path) -> Result<bool> {
+        self.lists.favorites.remove(path)
+    }
+
+    /// Ban `path`, so that it will not be returned by [FilelistCache::get] anymore
+    pub fn ban(&mut self, path: PathBuf) -> Result<bool> {
+        for filelist in &mut self.cache {
+            filelist.remove(&path);
+        }
+        self.lists.favorites.remove(&path)?;
+        self.lists.banned.insert(path)
+    }
+
+    /// Exclude `path` until it gets modified, because it could not be decoded
+    pub fn mark_failed(&mut self, path: PathBuf) {
+        for filelist in &mut self.cache {
+            filelist.remove(&path);
+        }
+        self.index.set_failed(&path);
+        self.schedule_index_save();
+        let mtime = mtime(&path);
+        self.lists.failed.insert(path, mtime);
+    }
+
+    /// Record the average color of `path`, now that it has been decoded, and its metadata read
+    /// by the decoding thread
+    pub fn image_decoded(&mut self, path: &Path, average_color: [u8; 3], entry: MediaEntry) {
+        self.index.set_decoded(path, average_color, entry);
+        self.schedule_index_save();
+    }
+
+    /// Forget the images outside of the watched directories, like the ones set with
+    /// `wpaperctl set`, unless they are in `current`
+    pub fn forget_unwatched(&mut self, current: &[PathBuf]) {
+        let cache = &self.cache;
+        let removed = self.index.retain(|path| {
+            cache
+                .iter()
+                .any(|filelist| path.starts_with(&filelist.path))
+                || current.iter().any(|current| current == path)
+        });
+        if removed {
+            self.schedule_index_save();
+        }
+    }
+
+    /// Write the index to disk
+    pub fn save_index(&self) {
+        self.index.save();
+    }
+
     /// paths must be sorted
     pub fn update_paths(
         &mut self,
@@ -132,22 +533,31 @@ impl FilelistCache {
                 }
                 let filelist = Filelist::new(&path, recursive);
                 let outdated = filelist.outdated.clone();
+                let changes = filelist.changes.clone();
                 self.cache.push(filelist);
                 let ping_clone = event_loop_ping.clone();
                 if let Err(err) = hotwatch
-                    .watch(&path, move |event| match event.kind {
-                        hotwatch::EventKind::Create(_)
-                        | hotwatch::EventKind::Remove(_)
-                        | hotwatch::EventKind::Modify(_) => {
-                            // We could manually update the list of files with the information
-                            // we get here, but the inotify on linux is not reliable,
-                            // so we prefer to always trigger an update and just reload
-                            // the entire list
-                            // See: https://github.com/notify-rs/notify/issues/412
+                    .watch(&path, move |event| {
+                        // The inotify on linux is not reliable, reload the entire list when
+                        // some events have been lost.
+                        // See: https://github.com/notify-rs/notify/issues/412
+                        if event.need_rescan() {
                             outdated.store(true, Ordering::Release);
                             ping_clone.ping();
+                            return;
+                        }
+                        match event.kind {
+                            // Metadata changes do not affect the list of files
+                            EventKind::Modify(ModifyKind::Metadata(_)) => {}
+                            EventKind::Create(_) | EventKind::Remove(_) | EventKind::Modify(_) => {
+                                let mut changes = changes.lock().unwrap();
+                                changes.paths.extend(event.paths.iter().cloned());
+                                changes.last_change = Some(Instant::now());
+                                drop(changes);
+                                ping_clone.ping();
+                            }
+                            _ => {}
                         }
-                        _ => {}
                     })
                     .wrap_err_with(|| format!("Failed to watch for changes for file {:?}", &path))
                 {
@@ -159,11 +569,317 @@ impl FilelistCache {
         self.update_cache();
     }
 
-    pub fn update_cache(&mut self) {
+    /// Read all the directories again
+    pub fn rescan(&mut self) {
+        for filelist in &self.cache {
+            filelist.outdated.store(true, Ordering::Relaxed);
+        }
+        self.update_cache();
+    }
+
+    /// Start reading the outdated directories and receive the ones that have been read.
+    /// Return true if any filelist has been updated.
+    pub fn update_cache(&mut self) -> bool {
         for filelist in &mut self.cache {
-            if filelist.outdated.load(std::sync::atomic::Ordering::Relaxed) {
-                filelist.populate();
+            // Changes happening during the scan will trigger another one
+            if !filelist.scanning && filelist.outdated.swap(false, Ordering::AcqRel) {
+                filelist.scanning = true;
+                let path = filelist.path.clone();
+                let recursive = filelist.recursive;
+                let sender = self.scan_sender.clone();
+                let ping = self.ping.clone();
+                let index = self.index.entries();
+                // Reading big directories can be slow, the current filelist is used until then
+                rayon::spawn(move || {
+                    let files = walk(&path, recursive, &path, &index).collect();
+                    if sender
+                        .send(Scan {
+                            path,
+                            recursive,
+                            files,
+                        })
+                        .is_ok()
+                    {
+                        ping.ping();
+                    }
+                });
             }
         }
+
+        let mut scanned = Vec::new();
+        while let Ok(scan) = self.scan_receiver.try_recv() {
+            // Give another chance to the images that failed and have been modified since
+            self.lists.failed.forget_changed();
+            // The path might have been removed from the config in the meantime
+            if let Some(filelist) = self
+                .cache
+                .iter_mut()
+                .find(|filelist| filelist.path == scan.path && filelist.recursive == scan.recursive)
+            {
+                self.index
+                    .prune(&scan.path, scan.recursive == Recursive::On, &scan.files);
+                filelist.publish(scan.files, &self.lists);
+                scanned.push(filelist.filelist.clone());
+            }
+        }
+        let mut changed = Vec::new();
+        while let Ok(changes) = self.changes_receiver.try_recv() {
+            if let Some(filelist) = self.cache.iter_mut().find(|filelist| {
+                filelist.path == changes.path && filelist.recursive == changes.recursive
+            }) {
+                filelist.applying_changes = false;
+                self.lists.failed.forget_changed();
+                changed.extend(filelist.apply_changes(
+                    &changes.changed,
+                    changes.found,
+                    &self.lists,
+                ));
+            }
+        }
+        let updated = !scanned.is_empty() || !changed.is_empty();
+        for files in scanned {
+            self.read_headers(files);
+        }
+        if !changed.is_empty() {
+            self.read_headers(Arc::new(changed));
+        }
+
+        let mut new_headers = false;
+        while let Ok(headers) = self.headers_receiver.try_recv() {
+            self.index.extend(headers);
+            self.headers_generation += 1;
+            new_headers = true;
+        }
+        if new_headers {
+            for filelist in &self.cache {
+                filelist.index_updated();
+            }
+        }
+        if updated || new_headers {
+            self.schedule_index_save();
+        }
+
+        self.schedule_changes();
+        updated
+    }
+
+    /// Apply the changes received by the watchers once they stop coming in
+    fn schedule_changes(&mut self) {
+        if self.changes_scheduled
+            || self
+                .cache
+                .iter()
+                .all(|filelist| filelist.changes.lock().unwrap().last_change.is_none())
+        {
+            return;
+        }
+
+        self.changes_scheduled = true;
+        if let Err(err) = self
+            .event_loop_handle
+            .insert_source(
+                Timer::from_duration(Self::CHANGES_DEBOUNCE),
+                |_, _, wpaperd| {
+                    let mut filelist_cache = wpaperd.filelist_cache.borrow_mut();
+                    let next_check = filelist_cache.apply_changes();
+                    if next_check.is_none() {
+                        filelist_cache.changes_scheduled = false;
+                    }
+                    match next_check {
+                        Some(next_check) => TimeoutAction::ToInstant(next_check),
+                        None => TimeoutAction::Drop,
+                    }
+                },
+            )
+            .map_err(|e| eyre!("{e:?}"))
+            .wrap_err("Failed to insert the filelist changes timer in the event loop")
+        {
+            error!("{err:?}");
+            self.changes_scheduled = false;
+        }
+    }
+
+    /// Start reading the pending changes of the directories that didn't change for
+    /// [FilelistCache::CHANGES_DEBOUNCE], they are applied by [FilelistCache::update_cache].
+    /// Return when the remaining changes should be read.
+    fn apply_changes(&mut self) -> Option<Instant> {
+        let mut next_check: Option<Instant> = None;
+        for filelist in &mut self.cache {
+            // The changes are applied on top of the new filelist
+            if filelist.scanning || filelist.applying_changes {
+                let instant = Instant::now() + Self::CHANGES_DEBOUNCE;
+                next_check = Some(next_check.map_or(instant, |next| next.min(instant)));
+                continue;
+            }
+            match filelist.take_changes(Self::CHANGES_DEBOUNCE) {
+                Ok(changed) => {
+                    filelist.applying_changes = true;
+                    let path = filelist.path.clone();
+                    let recursive = filelist.recursive;
+                    let sender = self.changes_sender.clone();
+                    let ping = self.ping.clone();
+                    let index = self.index.entries();
+                    // A directory copied at once can contain many images to check
+                    rayon::spawn(move || {
+                        let found = find_changes(&path, recursive, &changed, &index);
+                        if sender
+                            .send(ChangesScan {
+                                path,
+                                recursive,
+                                changed,
+                                found,
+                            })
+                            .is_ok()
+                        {
+                            ping.ping();
+                        }
+                    });
+                }
+                Err(Some(instant)) => {
+                    next_check = Some(next_check.map_or(instant, |next| next.min(instant)));
+                }
+                Err(None) => {}
+            }
+        }
+        next_check
+    }
+
+    /// Write the index to disk once no change has happened for [FilelistCache::INDEX_SAVE_DELAY]
+    fn schedule_index_save(&mut self) {
+        if self.index_save_scheduled {
+            return;
+        }
+
+        self.index_save_scheduled = true;
+        if let Err(err) = self
+            .event_loop_handle
+            .insert_source(
+                Timer::from_duration(Self::INDEX_SAVE_DELAY),
+                |_, _, wpaperd| {
+                    let mut filelist_cache = wpaperd.filelist_cache.borrow_mut();
+                    filelist_cache.index_save_scheduled = false;
+                    filelist_cache.index.save_in_background();
+                    TimeoutAction::Drop
+                },
+            )
+            .map_err(|e| eyre!("{e:?}"))
+            .wrap_err("Failed to insert the index save timer in the event loop")
+        {
+            error!("{err:?}");
+            self.index_save_scheduled = false;
+        }
+    }
+
+    /// Read the headers of the files that are new or have been modified in a background thread.
+    /// The event loop gets pinged when they are available.
+    fn read_headers(&self, files: Arc<Vec<PathBuf>>) {
+        let index = self.index.entries();
+        let sender = self.headers_sender.clone();
+        let ping = self.ping.clone();
+        // Use a single thread, so that loading the wallpapers is not delayed
+        rayon::spawn(move || {
+            let headers: Vec<_> = files
+                .iter()
+                .filter_map(|file| {
+                    let key = FileKey::new(file)?;
+                    if index.get(file).is_some_and(|entry| entry.key == key) {
+                        return None;
+                    }
+                    Some((file.clone(), MediaEntry::probe(file, key)))
+                })
+                .collect();
+            if !headers.is_empty() && sender.send(headers).is_ok() {
+                ping.ping();
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_natural_cmp() {
+        let mut files = vec!["img10.jpg", "img2.jpg", "img1.jpg", "img02.jpg", "a.jpg"];
+        files.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            files,
+            vec!["a.jpg", "img1.jpg", "img2.jpg", "img02.jpg", "img10.jpg"]
+        );
+    }
+
+    #[test]
+    fn test_natural_cmp_directories() {
+        assert_eq!(
+            natural_cmp("/wallpapers/2/img10.jpg", "/wallpapers/10/img2.jpg"),
+            CmpOrdering::Less
+        );
+        assert_eq!(natural_cmp("img", "img1"), CmpOrdering::Less);
+        assert_eq!(natural_cmp("img1", "img1"), CmpOrdering::Equal);
+    }
+
+    #[test]
+    fn test_sort_files() {
+        use crate::media_index::DecodeStatus;
+
+        let entry = |secs, exif_date| MediaEntry {
+            key: FileKey {
+                mtime: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs)),
+                size: 0,
+            },
+            dimensions: None,
+            orientation: None,
+            exif_date,
+            average_color: None,
+            status: DecodeStatus::Unknown,
+        };
+        // The files don't exist, the keys only come from the index
+        let files = ["/a.jpg", "/b.jpg", "/c.jpg", "/d.jpg"].map(PathBuf::from);
+        let index = HashMap::from([
+            (files[0].clone(), entry(30, None)),
+            (files[1].clone(), entry(20, Some((2020, 1, 1, 0, 0, 0)))),
+            (files[2].clone(), entry(10, Some((2019, 1, 1, 0, 0, 0)))),
+        ]);
+
+        let sorted = sort_files(&files, SortKey::Mtime, &index);
+        assert_eq!(sorted, [2, 1, 0, 3].map(|i| files[i].clone()));
+        // Images without a date come last, ordered by modification time
+        let sorted = sort_files(&files, SortKey::Exif, &index);
+        assert_eq!(sorted, [2, 1, 0, 3].map(|i| files[i].clone()));
+    }
+
+    #[test]
+    fn test_apply_changes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        let create_image = |file| std::fs::write(dir.join(file), b"\x89PNG\r\n\x1a\n").unwrap();
+        for file in ["a.png", "b.png", "nested/c.png"] {
+            create_image(file);
+        }
+        // Files are detected by their content
+        std::fs::File::create(dir.join("empty.png")).unwrap();
+        let lists = ImageLists::default();
+        let mut filelist = Filelist::new(dir, Recursive::On);
+        let index = HashMap::new();
+        filelist.publish(walk(dir, Recursive::On, dir, &index).collect(), &lists);
+        assert_eq!(filelist.filelist.len(), 3);
+
+        // Add an image, remove one and a whole directory
+        create_image("a0.png");
+        std::fs::remove_file(dir.join("b.png")).unwrap();
+        std::fs::remove_dir_all(dir.join("nested")).unwrap();
+        let paths = ["a0.png", "b.png", "nested"]
+            .iter()
+            .map(|file| dir.join(file))
+            .collect();
+        let found = find_changes(dir, Recursive::On, &paths, &index);
+        let changed = filelist.apply_changes(&paths, found, &lists);
+        assert_eq!(changed, vec![dir.join("a0.png")]);
+        assert_eq!(
+            *filelist.filelist,
+            vec![dir.join("a.png"), dir.join("a0.png")]
+        );
     }
 }
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use crate::{
+    image_loader::{DecodedImage, ImageKey},
+    media_index::FileKey,
+};
+
+struct CachedImage {
+    image: DecodedImage,
+    /// The image is outdated when the file changes
+    file_key: Option<FileKey>,
+    size: usize,
+    /// Value of [ImageCache::clock] the last time the image has been used
+    last_used: u64,
+}
+
+/// Decoded images kept in memory, so that showing them again doesn't require decoding them.
+/// The least recently used images are dropped to stay within the memory budget.
+pub struct ImageCache {
+    images: HashMap<ImageKey, CachedImage>,
+    /// Maximum number of bytes used by the images
+    budget: usize,
+    used: usize,
+    /// Incremented at every access
+    clock: u64,
+}
+
+impl ImageCache {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            images: HashMap::new(),
+            budget,
+            used: 0,
+            clock: 0,
+        }
+    }
+
+    pub fn get(&mut self, key: &ImageKey) -> Option<DecodedImage> {
+        let cached = self.images.get_mut(key)?;
+        if cached.file_key != FileKey::new(&key.path) {
+            self.remove(key);
+            return None;
+        }
+        self.clock += 1;
+        cached.last_used = self.clock;
+        Some(cached.image.clone())
+    }
+
+    #[inline]
+    pub fn contains(&self, key: &ImageKey) -> bool {
+        self.images.contains_key(key)
+    }
+
+    pub fn insert(&mut self, key: ImageKey, image: DecodedImage) {
+        self.remove(&key);
+        let size = image.size();
+        // It would evict everything else
+        if size > self.budget {
+            return;
+        }
+        while self.used + size > self.budget {
+            let oldest = self
+                .images
+                .iter()
+                .min_by_key(|(_, cached)| cached.last_used)
+                .map(|(key, _)| key.clone())
+                .expect("the cache is not empty while it's over budget");
+            self.remove(&oldest);
+        }
+
+        self.clock += 1;
+        self.used += size;
+        let file_key = FileKey::new(&key.path);
+        self.images.insert(
+            key,
+            CachedImage {
+                image,
+                file_key,
+                size,
+                last_used: self.clock,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, key: &ImageKey) {
+        if let Some(cached) = self.images.remove(key) {
+            self.used -= cached.size;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc};
+
+    use image::{DynamicImage, RgbaImage};
+
+    use super::*;
+    use crate::image_loader::TargetSize;
+
+    fn key(path: &str) -> ImageKey {
+        ImageKey {
+            path: PathBuf::from(path),
+            size: TargetSize::Original,
+            color_profile: None,
+        }
+    }
+
+    fn image(width: u32) -> DecodedImage {
+        DecodedImage {
+            image: Arc::new(DynamicImage::ImageRgba8(RgbaImage::new(width, 1))),
+            animation: None,
+            average_color: [0; 3],
+            entry: None,
+        }
+    }
+
+    #[test]
+    fn test_evict_least_recently_used() {
+        // Room for 3 images of 4 pixels
+        let mut cache = ImageCache::new(3 * 4 * 4);
+        for path in ["a", "b", "c"] {
+            cache.insert(key(path), image(4));
+        }
+        assert!(cache.get(&key("a")).is_some());
+
+        cache.insert(key("d"), image(4));
+        assert!(cache.contains(&key("a")));
+        assert!(!cache.contains(&key("b")));
+        assert!(cache.contains(&key("c")));
+        assert!(cache.contains(&key("d")));
+
+        // Make room for a bigger image
+        cache.insert(key("e"), image(8));
+        assert!(!cache.contains(&key("a")));
+        assert!(!cache.contains(&key("c")));
+        assert!(cache.contains(&key("d")));
+        assert_eq!(cache.used, 3 * 4 * 4);
+
+        // Images bigger than the budget are not cached
+        cache.insert(key("f"), image(16));
+        assert!(!cache.contains(&key("f")));
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{
+    display_info::DisplayInfo,
+    filelist_cache::Dimensions,
+    wallpaper_info::{MinResolution, WallpaperInfo},
+};
+
+/// Restrict the images that can be picked for a display, based on their dimensions
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ImageFilter {
+    /// Aspect ratio of the display (width / height)
+    ratio: f32,
+    match_orientation: bool,
+    aspect_tolerance: Option<f32>,
+    min_resolution: Option<Dimensions>,
+}
+
+impl ImageFilter {
+    pub fn new(wallpaper_info: &WallpaperInfo, display_info: &DisplayInfo) -> Self {
+        // We can't filter anything until we know the size of the display
+        if !display_info.is_configured() {
+            return Self::default();
+        }
+
+        Self {
+            ratio: display_info.ratio(),
+            match_orientation: wallpaper_info.match_orientation,
+            aspect_tolerance: wallpaper_info.aspect_tolerance,
+            min_resolution: wallpaper_info.min_resolution.map(
+                |min_resolution| match min_resolution {
+                    MinResolution::Native => (
+                        display_info.adjusted_width() as u32,
+                        display_info.adjusted_height() as u32,
+                    ),
+                    MinResolution::Size { width, height } => (width, height),
+                },
+            ),
+        }
+    }
+
+    /// Return true if every image matches
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        !self.match_orientation && self.aspect_tolerance.is_none() && self.min_resolution.is_none()
+    }
+
+    pub fn matches(&self, (width, height): Dimensions) -> bool {
+        if width == 0 || height == 0 {
+            return false;
+        }
+        if let Some((min_width, min_height)) = self.min_resolution {
+            if width < min_width || height < min_height {
+                return false;
+            }
+        }
+        let ratio = width as f32 / height as f32;
+
+        // Square images and displays match both orientations
+        let same_orientation =
+            ratio == 1.0 || self.ratio == 1.0 || (ratio > 1.0) == (self.ratio > 1.0);
+        if self.match_orientation && !same_orientation {
+            return false;
+        }
+
+        self.aspect_tolerance.map_or(true, |tolerance| {
+            (ratio / self.ratio - 1.0).abs() <= tolerance
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(ratio: f32, match_orientation: bool, aspect_tolerance: Option<f32>) -> ImageFilter {
+        ImageFilter {
+            ratio,
+            match_orientation,
+            aspect_tolerance,
+            min_resolution: None,
+        }
+    }
+
+    #[test]
+    fn test_match_orientation() {
+        let portrait = filter(9.0 / 16.0, true, None);
+        assert!(portrait.matches((1080, 1920)));
+        assert!(portrait.matches((1000, 1000)));
+        assert!(!portrait.matches((1920, 1080)));
+    }
+
+    #[test]
+    fn test_aspect_tolerance() {
+        let landscape = filter(16.0 / 9.0, false, Some(0.1));
+        assert!(landscape.matches((3840, 2160)));
+        // 16:10 is within 10% of 16:9
+        assert!(landscape.matches((1920, 1200)));
+        assert!(!landscape.matches((1600, 1200)));
+        assert!(!landscape.matches((0, 1200)));
+    }
+
+    #[test]
+    fn test_min_resolution() {
+        let filter = ImageFilter {
+            min_resolution: Some((3840, 2160)),
+            ..filter(16.0 / 9.0, false, None)
+        };
+        assert!(filter.matches((3840, 2160)));
+        assert!(filter.matches((5120, 2880)));
+        assert!(!filter.matches((1920, 1080)));
+        assert!(!filter.matches((3840, 1600)));
+    }
+}
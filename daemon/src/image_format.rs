@@ -0,0 +1,115 @@
+use std::{fs::File, io::Read, path::Path};
+
+use image::{metadata::Orientation, ImageDecoder, ImageFormat, ImageResult};
+
+/// Enough bytes to recognize the signature of every format known by [image::guess_format]
+const HEADER_LEN: u64 = 32;
+
+/// Detect the format of an image from its first bytes, instead of trusting its extension.
+/// Return None if the format is unknown or its decoder has not been compiled in.
+pub fn detect(path: &Path) -> Option<ImageFormat> {
+    let mut header = Vec::with_capacity(HEADER_LEN as usize);
+    File::open(path)
+        .ok()?
+        .take(HEADER_LEN)
+        .read_to_end(&mut header)
+        .ok()?;
+    let format = image::guess_format(&header).ok().or_else(|| {
+        // TGA files have no signature, the extension is all we have
+        ImageFormat::from_path(path)
+            .ok()
+            .filter(|format| *format == ImageFormat::Tga)
+    })?;
+    format.reading_enabled().then_some(format)
+}
+
+/// Return true if `path` is an SVG. SVGs are text files without a signature, they are
+/// recognized by their extension
+#[cfg(feature = "svg")]
+pub fn is_svg(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("svg"))
+}
+
+#[cfg(not(feature = "svg"))]
+#[inline]
+pub fn is_svg(_path: &Path) -> bool {
+    false
+}
+
+/// Return true if `path` is a JPEG XL image, either a bare codestream or a container
+#[cfg(feature = "jxl")]
+pub fn is_jxl(path: &Path) -> bool {
+    const CODESTREAM_SIGNATURE: &[u8] = b"\xff\x0a";
+    const CONTAINER_SIGNATURE: &[u8] = b"\0\0\0\x0cJXL \x0d\x0a\x87\x0a";
+    let mut header = Vec::with_capacity(CONTAINER_SIGNATURE.len());
+    File::open(path)
+        .and_then(|file| {
+            file.take(CONTAINER_SIGNATURE.len() as u64)
+                .read_to_end(&mut header)
+        })
+        .is_ok_and(|_| {
+            header.starts_with(CODESTREAM_SIGNATURE) || header.starts_with(CONTAINER_SIGNATURE)
+        })
+}
+
+#[cfg(not(feature = "jxl"))]
+#[inline]
+pub fn is_jxl(_path: &Path) -> bool {
+    false
+}
+
+/// Return true if `path` is an image that can be decoded
+#[inline]
+pub fn is_supported(path: &Path) -> bool {
+    is_svg(path) || is_jxl(path) || detect(path).is_some()
+}
+
+/// Return the orientation that has to be applied to the image read by `decoder`
+pub fn orientation(path: &Path, decoder: &mut impl ImageDecoder) -> ImageResult<Orientation> {
+    // The JPEG XL decoder already applies the orientation stored in the header of the image, and
+    // the EXIF one must be ignored
+    if is_jxl(path) {
+        return Ok(Orientation::NoTransforms);
+    }
+    decoder.orientation()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let png_signature = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        // The extension doesn't matter, only the content
+        std::fs::write(dir.join("image.jpg"), png_signature).unwrap();
+        std::fs::write(dir.join("image"), png_signature).unwrap();
+        std::fs::write(dir.join("notes.png"), "not an image").unwrap();
+        std::fs::write(dir.join("empty.png"), "").unwrap();
+
+        assert_eq!(detect(&dir.join("image.jpg")), Some(ImageFormat::Png));
+        assert_eq!(detect(&dir.join("image")), Some(ImageFormat::Png));
+        assert_eq!(detect(&dir.join("notes.png")), None);
+        assert_eq!(detect(&dir.join("empty.png")), None);
+        assert_eq!(detect(&dir.join("missing.png")), None);
+    }
+
+    #[cfg(feature = "jxl")]
+    #[test]
+    fn test_is_jxl() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::write(dir.join("codestream"), b"\xff\x0a\xfa\x1f").unwrap();
+        std::fs::write(dir.join("container"), b"\0\0\0\x0cJXL \x0d\x0a\x87\x0a\0").unwrap();
+        std::fs::write(dir.join("image.jxl"), b"\x89PNG\r\n\x1a\n").unwrap();
+
+        assert!(is_jxl(&dir.join("codestream")));
+        assert!(is_jxl(&dir.join("container")));
+        assert!(is_supported(&dir.join("container")));
+        // The extension doesn't matter
+        assert!(!is_jxl(&dir.join("image.jxl")));
+    }
+}
@@ -0,0 +1,154 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use color_eyre::{eyre::WrapErr, Result};
+use log::warn;
+use xdg::BaseDirectories;
+
+use crate::{filelist_cache::mtime, state::write_atomically};
+
+/// A list of images saved on disk, one path per line
+#[derive(Debug, Default)]
+pub struct ImageList {
+    paths: HashSet<PathBuf>,
+    /// Where the list is saved. The list is not saved when it's None
+    file: Option<PathBuf>,
+}
+
+impl ImageList {
+    /// Load the list from `file`. A missing file results in an empty list
+    fn load(file: PathBuf) -> Self {
+        let paths = if file.exists() {
+            match fs::read_to_string(&file)
+                .wrap_err_with(|| format!("Failed to read the image list {file:?}"))
+            {
+                Ok(content) => content
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .map(PathBuf::from)
+                    .collect(),
+                Err(err) => {
+                    warn!("{err:?}");
+                    HashSet::new()
+                }
+            }
+        } else {
+            HashSet::new()
+        };
+        Self {
+            paths,
+            file: Some(file),
+        }
+    }
+
+    #[inline]
+    pub fn contains(&self, path: &Path) -> bool {
+        self.paths.contains(path)
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    /// Add `path` to the list and save it. Return true if it wasn't already in the list
+    pub fn insert(&mut self, path: PathBuf) -> Result<bool> {
+        if !self.paths.insert(path) {
+            return Ok(false);
+        }
+        self.save().map(|_| true)
+    }
+
+    /// Remove `path` from the list and save it. Return true if it was in the list
+    pub fn remove(&mut self, path: &Path) -> Result<bool> {
+        if !self.paths.remove(path) {
+            return Ok(false);
+        }
+        self.save().map(|_| true)
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        let mut paths: Vec<_> = self
+            .paths
+            .iter()
+            .map(|path| path.to_string_lossy())
+            .collect();
+        // Keep the file stable, so that it can be easily edited and versioned by the user
+        paths.sort_unstable();
+        let mut content = paths.join("\n");
+        content.push('\n');
+        write_atomically(file, content.as_bytes())
+    }
+}
+
+/// Images that could not be decoded, with their modification time when that happened.
+/// Unlike [ImageList], it is kept in memory only
+#[derive(Debug, Default)]
+pub struct FailedImages(HashMap<PathBuf, Option<SystemTime>>);
+
+impl FailedImages {
+    #[inline]
+    pub fn contains(&self, path: &Path) -> bool {
+        self.0.contains_key(path)
+    }
+
+    /// `mtime` is the modification time of `path` when it failed
+    pub fn insert(&mut self, path: PathBuf, mtime: Option<SystemTime>) {
+        self.0.insert(path, mtime);
+    }
+
+    /// Forget the images that have been modified since they failed
+    pub fn forget_changed(&mut self) {
+        self.0
+            .retain(|path, failed_mtime| mtime(path) == *failed_mtime);
+    }
+}
+
+/// The images marked by the user via wpaperctl, and the ones that could not be decoded,
+/// shared between all the displays
+#[derive(Debug, Default)]
+pub struct ImageLists {
+    pub favorites: ImageList,
+    pub banned: ImageList,
+    pub failed: FailedImages,
+}
+
+impl ImageLists {
+    const FAVORITES_FILENAME: &'static str = "favorites";
+    const BANNED_FILENAME: &'static str = "banned";
+
+    /// Load the lists from $XDG_DATA_HOME/wpaperd
+    pub fn new(xdg_dirs: &BaseDirectories) -> Self {
+        let load = |filename| match xdg_dirs
+            .place_data_file(filename)
+            .wrap_err("Failed to create the data directory $XDG_DATA_HOME/wpaperd")
+        {
+            Ok(file) => ImageList::load(file),
+            Err(err) => {
+                warn!(
+                    "{:?}",
+                    err.wrap_err(format!("The {filename} list will not be saved"))
+                );
+                ImageList::default()
+            }
+        };
+        Self {
+            favorites: load(Self::FAVORITES_FILENAME),
+            banned: load(Self::BANNED_FILENAME),
+            failed: FailedImages::default(),
+        }
+    }
+
+    /// Return true if `path` must never be picked
+    #[inline]
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        self.banned.contains(path) || self.failed.contains(path)
+    }
+}
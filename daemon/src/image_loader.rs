@@ -1,52 +1,323 @@
 use std::{
     collections::HashMap,
+    fmt,
     fs::File,
     io::BufReader,
-    path::PathBuf,
-    sync::mpsc::{Receiver, TryRecvError},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, TryRecvError},
+        Arc, OnceLock,
+    },
+    time::{Duration, Instant},
 };
 
-use color_eyre::eyre::eyre;
-use image::{DynamicImage, ImageDecoder, ImageReader, RgbaImage};
+use color_eyre::{eyre::WrapErr, Report};
+use image::{
+    error::ImageError, imageops::FilterType, metadata::Orientation, DynamicImage, GrayImage,
+    ImageDecoder, ImageFormat, ImageReader, Limits, RgbImage, RgbaImage,
+};
 use log::warn;
-use smithay_client_toolkit::reexports::calloop::ping::Ping;
+use smithay_client_toolkit::reexports::calloop::{
+    ping::Ping,
+    timer::{TimeoutAction, Timer},
+    LoopHandle,
+};
+
+use crate::{
+    animation::Animation,
+    color::{self, ColorTransform},
+    display_info::DisplayInfo,
+    image_cache::ImageCache,
+    media_index::{FileKey, MediaEntry},
+    wallpaper_info::BackgroundMode,
+    Wpaperd,
+};
+
+/// Size of the area an image is drawn on, used to downscale the image after decoding it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TargetSize {
+    /// Keep the original size
+    Original,
+    /// The image covers the whole area, the parts outside of it are cropped
+    Cover { width: u32, height: u32 },
+    /// The whole image fits inside the area
+    Contain { width: u32, height: u32 },
+}
+
+impl TargetSize {
+    pub fn new(mode: BackgroundMode, display_info: &DisplayInfo) -> Self {
+        let (width, height) = (display_info.scaled_width(), display_info.scaled_height());
+        // The display has not been configured yet
+        if width <= 0 || height <= 0 {
+            return Self::Original;
+        }
+        let (width, height) = (width as u32, height as u32);
+        match mode {
+            BackgroundMode::Stretch | BackgroundMode::Center => Self::Cover { width, height },
+            BackgroundMode::Fit | BackgroundMode::FitBorderColor => Self::Contain { width, height },
+            // The image is repeated at its original size
+            BackgroundMode::Tile => Self::Original,
+            // The image covers the canvas of all the displays in the group
+            BackgroundMode::Span => match display_info.span_area {
+                Some(area) => Self::Cover {
+                    width: (width as f32 / area.width).ceil() as u32,
+                    height: (height as f32 / area.height).ceil() as u32,
+                },
+                None => Self::Cover { width, height },
+            },
+        }
+    }
 
-type ImageData = Option<RgbaImage>;
+    /// Return how much an image of `width`x`height` has to be scaled to cover or fit the area
+    fn factor(self, width: u32, height: u32) -> Option<f64> {
+        let (scale_width, scale_height) = match self {
+            Self::Original => return None,
+            Self::Cover {
+                width: area_width,
+                height: area_height,
+            }
+            | Self::Contain {
+                width: area_width,
+                height: area_height,
+            } => (
+                area_width as f64 / width as f64,
+                area_height as f64 / height as f64,
+            ),
+        };
+        Some(match self {
+            Self::Cover { .. } => scale_width.max(scale_height),
+            _ => scale_width.min(scale_height),
+        })
+    }
+
+    /// Return the size of an image of `width`x`height` once scaled to cover or fit the area,
+    /// None if it keeps its original size
+    pub fn scale(self, width: u32, height: u32) -> Option<(u32, u32)> {
+        self.factor(width, height).map(|factor| {
+            (
+                ((width as f64 * factor).round() as u32).max(1),
+                ((height as f64 * factor).round() as u32).max(1),
+            )
+        })
+    }
+
+    /// Same as [TargetSize::scale], but None if the image is not bigger than the area
+    fn downscale(self, width: u32, height: u32) -> Option<(u32, u32)> {
+        self.factor(width, height)
+            .filter(|factor| *factor < 1.0)
+            .and(self.scale(width, height))
+    }
+
+    /// Downscale `image` if it's bigger than the area
+    pub fn resize(self, image: DynamicImage) -> DynamicImage {
+        match self.downscale(image.width(), image.height()) {
+            Some((width, height)) => image.resize_exact(width, height, FilterType::Lanczos3),
+            None => image,
+        }
+    }
+}
+
+/// An image decoded for a specific size and color profile
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ImageKey {
+    pub path: PathBuf,
+    pub size: TargetSize,
+    /// The ICC profile the colors are converted to, sRGB if None
+    pub color_profile: Option<PathBuf>,
+}
+
+#[derive(Clone)]
+pub struct DecodedImage {
+    /// Shared with the [ImageCache]
+    pub image: Arc<DynamicImage>,
+    /// All the frames, when the image is animated. The first one is `image`
+    pub animation: Option<Arc<Animation>>,
+    /// Saved in the index, it's cheap to compute while the image is still in the cache
+    pub average_color: [u8; 3],
+    /// Metadata saved in the index, read by the decoding thread. None if the file is gone
+    pub entry: Option<MediaEntry>,
+}
+
+impl DecodedImage {
+    /// A static image
+    fn new(image: RgbaImage) -> Self {
+        let average_color = average_color(&image);
+        Self {
+            image: Arc::new(DynamicImage::ImageRgba8(image)),
+            animation: None,
+            average_color,
+            entry: None,
+        }
+    }
+
+    /// Memory used by the image, in bytes
+    pub fn size(&self) -> usize {
+        match &self.animation {
+            Some(animation) => animation.size(),
+            None => self.image.as_bytes().len(),
+        }
+    }
+}
+
+/// Safeguards against images that would use too much memory or time to be decoded
+#[derive(Debug, Clone, Copy)]
+pub struct DecodeLimits {
+    /// Maximum number of pixels of an image
+    pub max_pixels: u64,
+    /// Maximum memory used by the decoder, in bytes
+    pub max_memory: u64,
+    /// The images that take longer to decode are rejected
+    pub timeout: Duration,
+}
+
+impl DecodeLimits {
+    /// Limits enforced by the decoders of image
+    pub fn image_limits(&self) -> Limits {
+        let mut limits = Limits::default();
+        limits.max_alloc = Some(self.max_memory);
+        limits
+    }
+
+    /// Reject the images with more than [DecodeLimits::max_pixels]
+    pub fn check_dimensions(&self, width: u32, height: u32) -> Result<(), LimitExceeded> {
+        if width as u64 * height as u64 > self.max_pixels {
+            return Err(LimitExceeded(format!(
+                "the image is {width}x{height}, more than {} pixels",
+                self.max_pixels
+            )));
+        }
+        Ok(())
+    }
+
+    /// Reject the images using more than [DecodeLimits::max_memory]
+    pub fn check_memory(&self, size: u64) -> Result<(), LimitExceeded> {
+        if size > self.max_memory {
+            return Err(LimitExceeded(format!(
+                "the decoded image uses {} MiB, more than {} MiB",
+                size / 1024 / 1024,
+                self.max_memory / 1024 / 1024
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// The image exceeds the [DecodeLimits]
+#[derive(Debug)]
+pub struct LimitExceeded(String);
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// Why an image could not be decoded
+enum DecodeError {
+    Failed,
+    /// The image exceeds the [DecodeLimits], the reason is shown by `wpaperctl status`
+    Rejected(String),
+}
+
+type ImageData = Option<DecodedImage>;
 
 struct Image {
     data: ImageData,
-    receiver: Option<Receiver<ImageData>>,
+    receiver: Option<Receiver<Result<DecodedImage, DecodeError>>>,
     requesters: Vec<String>,
+    /// Nobody needs the image anymore, skip decoding it if it didn't start yet
+    cancelled: Arc<AtomicBool>,
+    /// Set by the thread once it starts decoding the image
+    started: Arc<OnceLock<Instant>>,
+    /// The image took longer than [DecodeLimits::timeout] to decode
+    timed_out: bool,
 }
 
 pub enum ImageLoaderStatus {
-    Loaded(RgbaImage),
+    Loaded(DecodedImage),
     Waiting,
     Error,
 }
 
+/// How many images requested by a display were already decoded
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
 pub struct ImageLoader {
-    images: HashMap<PathBuf, Image>,
+    /// Images being decoded, or decoded and not consumed by all their requesters yet
+    images: HashMap<ImageKey, Image>,
+    cache: ImageCache,
+    /// Statistics of the cache for each requester
+    stats: HashMap<String, CacheStats>,
+    /// The last images rejected for each requester, with the reason
+    rejected: HashMap<String, Vec<(PathBuf, String)>>,
+    limits: DecodeLimits,
+    event_loop_handle: LoopHandle<'static, Wpaperd>,
     ping: Ping,
 }
 
 impl ImageLoader {
-    pub fn new(ping: Ping) -> Self {
+    /// Start decoding the next image this long before it's shown
+    pub const DEFAULT_PREFETCH: Duration = Duration::from_secs(5);
+    /// Number of rejected images remembered for each requester
+    const MAX_REJECTED: usize = 10;
+
+    /// `cache_size` is the memory used to keep the decoded images, in bytes
+    pub fn new(
+        event_loop_handle: LoopHandle<'static, Wpaperd>,
+        ping: Ping,
+        cache_size: usize,
+        limits: DecodeLimits,
+    ) -> Self {
         Self {
             images: HashMap::new(),
+            cache: ImageCache::new(cache_size),
+            stats: HashMap::new(),
+            rejected: HashMap::new(),
+            limits,
+            event_loop_handle,
             ping,
         }
     }
 
-    pub fn background_load(&mut self, path: PathBuf, requester_name: String) -> ImageLoaderStatus {
-        if let Some(image) = self.images.get_mut(&path) {
+    pub fn background_load(&mut self, key: ImageKey, requester_name: String) -> ImageLoaderStatus {
+        // The requester needed the image for another size or color profile before
+        let stale: Vec<ImageKey> = self
+            .images
+            .keys()
+            .filter(|other| other.path == key.path && **other != key)
+            .cloned()
+            .collect();
+        for other in stale {
+            self.cancel(&other, &requester_name);
+        }
+        if let Some(image) = self.images.get_mut(&key) {
             if let Some(rx) = image.receiver.take() {
                 match rx.try_recv() {
-                    Ok(Some(image_data)) => {
+                    Ok(Ok(image_data)) => {
+                        self.cache.insert(key.clone(), image_data.clone());
                         image.data = Some(image_data);
                     }
-                    Ok(None) | Err(TryRecvError::Disconnected) => {
-                        self.images.remove(&path);
+                    Ok(Err(DecodeError::Rejected(reason))) => {
+                        self.reject(&key, reason);
+                        return ImageLoaderStatus::Error;
+                    }
+                    Ok(Err(DecodeError::Failed)) | Err(TryRecvError::Disconnected) => {
+                        self.images.remove(&key);
+                        return ImageLoaderStatus::Error;
+                    }
+                    Err(TryRecvError::Empty) if image.timed_out => {
+                        let reason = format!(
+                            "decoding took longer than {} seconds",
+                            self.limits.timeout.as_secs()
+                        );
+                        self.reject(&key, reason);
                         return ImageLoaderStatus::Error;
                     }
                     Err(TryRecvError::Empty) => {
@@ -67,7 +338,7 @@ impl ImageLoader {
                     && image.requesters.first().unwrap() == &requester_name
                 {
                     // Just send it up and remove it from the map
-                    let image = self.images.remove(&path);
+                    let image = self.images.remove(&key);
                     ImageLoaderStatus::Loaded(image.unwrap().data.unwrap())
                 } else {
                     // otherwise this image has been requested by multiple surfaces
@@ -82,69 +353,171 @@ impl ImageLoader {
                 // The decoded image is not ready yet
                 ImageLoaderStatus::Waiting
             }
+        } else if let Some(image) = self.cache.get(&key) {
+            self.stats.entry(requester_name).or_default().hits += 1;
+            ImageLoaderStatus::Loaded(image)
         } else {
-            self.start_new_thread(path, requester_name);
+            self.start_new_thread(key, requester_name);
             ImageLoaderStatus::Waiting
         }
     }
 
-    fn start_new_thread(&mut self, path: PathBuf, requester_name: String) {
+    /// Return the statistics of the cache for `requester_name`
+    pub fn stats(&self, requester_name: &str) -> CacheStats {
+        self.stats.get(requester_name).copied().unwrap_or_default()
+    }
+
+    /// Return the last images rejected for `requester_name`, with the reason
+    pub fn rejected(&self, requester_name: &str) -> Vec<(PathBuf, String)> {
+        self.rejected
+            .get(requester_name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Drop the image and remember why it has been rejected
+    fn reject(&mut self, key: &ImageKey, reason: String) {
+        let Some(image) = self.images.remove(key) else {
+            return;
+        };
+        warn!(
+            "Rejected image {:?} needed for {}: {reason}",
+            key.path,
+            image.requesters.join(", ")
+        );
+        for requester in image.requesters {
+            let rejected = self.rejected.entry(requester).or_default();
+            if rejected.len() == Self::MAX_REJECTED {
+                rejected.remove(0);
+            }
+            rejected.push((key.path.clone(), reason.clone()));
+        }
+    }
+
+    /// Check whether the image has been decoding for longer than [DecodeLimits::timeout].
+    /// The decoding thread can't be stopped, its result is ignored
+    fn check_timeout(&mut self, key: &ImageKey) -> TimeoutAction {
+        let timeout = self.limits.timeout;
+        let Some(image) = self.images.get_mut(key) else {
+            return TimeoutAction::Drop;
+        };
+        if image.receiver.is_none() {
+            // Already decoded
+            return TimeoutAction::Drop;
+        }
+        match image.started.get() {
+            // Wait for a thread to pick the image up
+            None => TimeoutAction::ToDuration(timeout),
+            Some(started) if started.elapsed() < timeout => {
+                TimeoutAction::ToDuration(timeout - started.elapsed())
+            }
+            Some(_) => {
+                image.timed_out = true;
+                image.cancelled.store(true, Ordering::Relaxed);
+                // Let the requesters know that the image has been rejected
+                self.ping.ping();
+                TimeoutAction::Drop
+            }
+        }
+    }
+
+    /// Start decoding `path` before `requester_name` needs it. The image is returned by
+    /// [ImageLoader::background_load] without decoding it again
+    pub fn prefetch(&mut self, key: ImageKey, requester_name: String) {
+        match self.images.get_mut(&key) {
+            Some(image) => {
+                if !image.requesters.contains(&requester_name) {
+                    image.requesters.push(requester_name);
+                }
+            }
+            None if self.cache.contains(&key) => {}
+            None => self.start_new_thread(key, requester_name),
+        }
+    }
+
+    /// `requester_name` doesn't need `key` anymore. The image is dropped when nobody else does
+    pub fn cancel(&mut self, key: &ImageKey, requester_name: &str) {
+        let Some(image) = self.images.get_mut(key) else {
+            return;
+        };
+        image.requesters.retain(|name| name != requester_name);
+        if image.requesters.is_empty() {
+            image.cancelled.store(true, Ordering::Relaxed);
+            self.images.remove(key);
+        }
+    }
+
+    fn start_new_thread(&mut self, key: ImageKey, requester_name: String) {
+        self.stats.entry(requester_name.clone()).or_default().misses += 1;
         // Start loading a new image in a new thread
-        let path_clone = path.clone();
+        let key_clone = key.clone();
+        let limits = self.limits;
         let ping_clone = self.ping.clone();
         let requester_clone = requester_name.clone();
         let (tx, rx) = std::sync::mpsc::channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let cancelled_clone = cancelled.clone();
+        let started = Arc::new(OnceLock::new());
+        let started_clone = started.clone();
         rayon::spawn(move || {
-            match File::open(&path_clone)
-                .and_then(|file| ImageReader::new(BufReader::new(file)).with_guessed_format())
-            {
-                Ok(image) => {
-                    // Notify the event loop that the image has been loaded
-                    // We need this so that Surface::load_wallpaper is called even if
-                    // wl_surface::frame doesn't get called by the compositor (e.g. a window is
-                    // fullscreen)
-                    // Do the conversion first, then the ping, otherwise we will have a race
-                    // condition
-                    let result = (|| -> color_eyre::Result<RgbaImage> {
-                        let mut decoder = image.into_decoder()?;
-                        let orientation = decoder.orientation()?;
-                        let mut image = DynamicImage::from_decoder(decoder)?;
-                        image.apply_orientation(orientation);
-                        Ok(image.into_rgba8())
-                    })();
-                    match result {
-                        Ok(image) => {
-                            tx.send(Some(image)).unwrap();
-                            ping_clone.ping();
-                        }
-                        Err(err) => {
-                            warn!(
-                                "{:?}",
-                                err.wrap_err(format!(
-                                    "Failed to decode image {path_clone:?} needed for {requester_clone}"
-                                ))
-                            );
-                            tx.send(None).unwrap();
-                        }
+            if cancelled_clone.load(Ordering::Relaxed) {
+                return;
+            }
+            let _ = started_clone.set(Instant::now());
+            // Notify the event loop that the image has been loaded
+            // We need this so that Surface::load_wallpaper is called even if
+            // wl_surface::frame doesn't get called by the compositor (e.g. a window is
+            // fullscreen)
+            // Do the conversion first, then the ping, otherwise we will have a race
+            // condition
+            // The receiver is dropped when the image gets cancelled, ignore the send errors
+            match decode(&key_clone, &limits) {
+                Ok(mut image) => {
+                    // Read the header here, instead of on the event loop
+                    let path = &key_clone.path;
+                    image.entry = FileKey::new(path).map(|key| MediaEntry::probe(path, key));
+                    if tx.send(Ok(image)).is_ok() {
+                        ping_clone.ping();
+                    }
+                }
+                Err(err) if exceeds_limits(&err) => {
+                    if tx.send(Err(DecodeError::Rejected(err.to_string()))).is_ok() {
+                        ping_clone.ping();
                     }
                 }
                 Err(err) => {
                     warn!(
                         "{:?}",
-                        eyre!(err).wrap_err(format!(
-                            "Failed to read image {path_clone:?} needed for {requester_clone}"
+                        err.wrap_err(format!(
+                            "Failed to load image {:?} needed for {requester_clone}",
+                            key_clone.path
                         ))
                     );
-                    tx.send(None).unwrap();
+                    let _ = tx.send(Err(DecodeError::Failed));
                 }
             }
         });
+        let timeout_key = key.clone();
+        if let Err(err) = self.event_loop_handle.insert_source(
+            Timer::from_duration(self.limits.timeout),
+            move |_, _, wpaperd| {
+                wpaperd
+                    .image_loader
+                    .borrow_mut()
+                    .check_timeout(&timeout_key)
+            },
+        ) {
+            warn!("Failed to add the decoding timeout to the event loop: {err}");
+        }
         let image = Image {
             requesters: vec![requester_name],
             receiver: Some(rx),
             data: None,
+            cancelled,
+            started,
+            timed_out: false,
         };
-        self.images.insert(path, image);
+        self.images.insert(key, image);
     }
 
     /// Check that there are no threads waiting on zero requesters
@@ -156,3 +529,291 @@ impl ImageLoader {
             .any(|(_, image)| { image.requesters.is_empty() }));
     }
 }
+
+/// The image has been rejected by the [DecodeLimits] or by the limits of the decoder
+fn exceeds_limits(err: &Report) -> bool {
+    err.chain().any(|err| {
+        err.is::<LimitExceeded>() || matches!(err.downcast_ref(), Some(ImageError::Limits(_)))
+    })
+}
+
+/// Decode the image, then scale it and convert its colors as requested by `key`
+fn decode(key: &ImageKey, limits: &DecodeLimits) -> color_eyre::Result<DecodedImage> {
+    let (path, size) = (key.path.as_path(), key.size);
+    let color_profile = key.color_profile.as_deref();
+    #[cfg(feature = "svg")]
+    if crate::image_format::is_svg(path) {
+        let mut transform = ColorTransform::new(None, color_profile)?;
+        let image = crate::svg::rasterize(path, size, limits)?;
+        return Ok(DecodedImage::new(
+            color::convert(transform.as_mut(), image)?.into_rgba8(),
+        ));
+    }
+
+    let mut image = File::open(path)
+        .and_then(|file| ImageReader::new(BufReader::new(file)).with_guessed_format())
+        .wrap_err("Failed to read the image")?;
+    image.limits(limits.image_limits());
+    let format = image.format();
+    let animation = match format {
+        Some(format) => Animation::decode(path, format, size, color_profile, limits)?,
+        None => None,
+    };
+    if let Some(animation) = animation {
+        let first = animation.frames[0].image.clone();
+        let average_color = first.as_rgba8().map(average_color).unwrap_or_default();
+        return Ok(DecodedImage {
+            image: first,
+            // A GIF with a single frame is a static image
+            animation: (animation.frames.len() > 1).then(|| Arc::new(animation)),
+            average_color,
+            entry: None,
+        });
+    }
+    let mut decoder = image.into_decoder()?;
+    let (width, height) = decoder.dimensions();
+    limits.check_dimensions(width, height)?;
+    limits.check_memory(decoder.total_bytes())?;
+    let orientation = crate::image_format::orientation(path, &mut decoder)?;
+    let mut transform = ColorTransform::new(decoder.icc_profile()?.as_deref(), color_profile)?;
+    // Only JPEG images can be decoded at a reduced resolution, the other formats are always
+    // decoded at full size and downscaled afterwards
+    let mut image = match format
+        .filter(|format| *format == ImageFormat::Jpeg)
+        .and_then(|_| decode_jpeg_scaled(path, size, orientation))
+    {
+        Some(image) => image,
+        None => DynamicImage::from_decoder(decoder)?,
+    };
+    image.apply_orientation(orientation);
+    // Images with 16 bits per channel are only reduced to 8 bits at the end
+    let image = color::convert(transform.as_mut(), size.resize(image))?;
+    Ok(DecodedImage::new(image.into_rgba8()))
+}
+
+/// Decode a JPEG image at 1/2, 1/4 or 1/8 of its size, the smallest that still covers or fits
+/// `size`, by scaling the IDCT. None if the image is not bigger than `size` or can't be decoded
+/// this way, like the CMYK and 16 bits images
+fn decode_jpeg_scaled(
+    path: &Path,
+    size: TargetSize,
+    orientation: Orientation,
+) -> Option<DynamicImage> {
+    let file = File::open(path).ok()?;
+    let mut decoder = jpeg_decoder::Decoder::new(BufReader::new(file));
+    decoder.read_info().ok()?;
+    let info = decoder.info()?;
+    let (width, height) = (info.width as u32, info.height as u32);
+    // The size of the area applies to the image once rotated
+    let rotated = matches!(
+        orientation,
+        Orientation::Rotate90
+            | Orientation::Rotate270
+            | Orientation::Rotate90FlipH
+            | Orientation::Rotate270FlipH
+    );
+    let (target_width, target_height) = if rotated {
+        let (target_height, target_width) = size.downscale(height, width)?;
+        (target_width, target_height)
+    } else {
+        size.downscale(width, height)?
+    };
+    let (width, height) = decoder
+        .scale(target_width as u16, target_height as u16)
+        .ok()?;
+    let (width, height) = (width as u32, height as u32);
+    match info.pixel_format {
+        jpeg_decoder::PixelFormat::L8 => {
+            GrayImage::from_raw(width, height, decoder.decode().ok()?).map(DynamicImage::from)
+        }
+        jpeg_decoder::PixelFormat::RGB24 => {
+            RgbImage::from_raw(width, height, decoder.decode().ok()?).map(DynamicImage::from)
+        }
+        jpeg_decoder::PixelFormat::L16 | jpeg_decoder::PixelFormat::CMYK32 => None,
+    }
+}
+
+/// Average of the color channels of all the pixels, ignoring the alpha
+fn average_color(image: &RgbaImage) -> [u8; 3] {
+    let mut sum = [0u64; 3];
+    for pixel in image.pixels() {
+        for (sum, channel) in sum.iter_mut().zip(pixel.0) {
+            *sum += channel as u64;
+        }
+    }
+    let pixels = (image.width() as u64 * image.height() as u64).max(1);
+    sum.map(|sum| (sum / pixels) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smithay_client_toolkit::reexports::calloop::{ping::make_ping, EventLoop};
+
+    fn loader(event_loop: &EventLoop<'static, Wpaperd>) -> ImageLoader {
+        let (ping, _) = make_ping().unwrap();
+        ImageLoader::new(
+            event_loop.handle(),
+            ping,
+            1 << 20,
+            DecodeLimits {
+                max_pixels: u64::MAX,
+                max_memory: u64::MAX,
+                timeout: Duration::MAX,
+            },
+        )
+    }
+
+    fn image_key(path: PathBuf) -> ImageKey {
+        RgbaImage::new(4, 4).save(&path).unwrap();
+        ImageKey {
+            path,
+            size: TargetSize::Original,
+            color_profile: None,
+        }
+    }
+
+    /// Call [ImageLoader::background_load] until the thread decoding the image is done
+    fn wait_for(loader: &mut ImageLoader, key: &ImageKey) -> Option<DecodedImage> {
+        for _ in 0..500 {
+            match loader.background_load(key.clone(), "DP-1".to_string()) {
+                ImageLoaderStatus::Loaded(image) => return Some(image),
+                ImageLoaderStatus::Waiting => std::thread::sleep(Duration::from_millis(10)),
+                ImageLoaderStatus::Error => return None,
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn test_downscale() {
+        let cover = TargetSize::Cover {
+            width: 1920,
+            height: 1080,
+        };
+        let contain = TargetSize::Contain {
+            width: 1920,
+            height: 1080,
+        };
+        assert_eq!(cover.downscale(4000, 3000), Some((1920, 1440)));
+        assert_eq!(contain.downscale(4000, 3000), Some((1440, 1080)));
+        // Images are never upscaled
+        assert_eq!(cover.downscale(1920, 1000), None);
+        assert_eq!(contain.downscale(1000, 500), None);
+        assert_eq!(TargetSize::Original.downscale(4000, 3000), None);
+    }
+
+    #[test]
+    fn test_prefetch_hit() {
+        let tmp = tempfile::tempdir().unwrap();
+        let event_loop = EventLoop::try_new().unwrap();
+        let mut loader = loader(&event_loop);
+        let key = image_key(tmp.path().join("next.png"));
+
+        loader.prefetch(key.clone(), "DP-1".to_string());
+        // The next switch gets the image decoded by the prefetch
+        assert!(wait_for(&mut loader, &key).is_some());
+        assert_eq!(loader.stats("DP-1").misses, 1);
+        assert!(loader.images.is_empty());
+        // Then it stays in the cache
+        assert!(wait_for(&mut loader, &key).is_some());
+        assert_eq!(loader.stats("DP-1").hits, 1);
+        assert_eq!(loader.stats("DP-1").misses, 1);
+    }
+
+    #[test]
+    fn test_prefetch_cancelled() {
+        let tmp = tempfile::tempdir().unwrap();
+        let event_loop = EventLoop::try_new().unwrap();
+        let mut loader = loader(&event_loop);
+        let prefetched = image_key(tmp.path().join("next.png"));
+        let previous = image_key(tmp.path().join("previous.png"));
+
+        // The user switched to the previous image while the next one was prefetched
+        loader.prefetch(prefetched.clone(), "DP-1".to_string());
+        loader.cancel(&prefetched, "DP-1");
+        assert!(wait_for(&mut loader, &previous).is_some());
+        assert!(loader.images.is_empty());
+        assert!(!loader.cache.contains(&prefetched));
+        assert_eq!(loader.stats("DP-1").misses, 2);
+    }
+
+    #[test]
+    fn test_decode_jpeg_scaled() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("photo.jpg");
+        RgbImage::new(800, 600).save(&path).unwrap();
+        let scaled = |size| {
+            decode_jpeg_scaled(&path, size, Orientation::NoTransforms)
+                .map(|image| (image.width(), image.height()))
+        };
+
+        // The smallest scale that covers the area
+        let cover = TargetSize::Cover {
+            width: 200,
+            height: 100,
+        };
+        assert_eq!(scaled(cover), Some((200, 150)));
+        let contain = TargetSize::Contain {
+            width: 300,
+            height: 300,
+        };
+        assert_eq!(scaled(contain), Some((400, 300)));
+        assert_eq!(scaled(TargetSize::Original), None);
+
+        // Then it's downscaled to the exact size
+        let key = ImageKey {
+            path: path.clone(),
+            size: contain,
+            color_profile: None,
+        };
+        let limits = DecodeLimits {
+            max_pixels: u64::MAX,
+            max_memory: u64::MAX,
+            timeout: Duration::MAX,
+        };
+        let decoded = decode(&key, &limits).unwrap();
+        assert_eq!((decoded.image.width(), decoded.image.height()), (300, 225));
+    }
+
+    #[test]
+    fn test_decode_limits() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("image.png");
+        RgbaImage::new(20, 20).save(&path).unwrap();
+        let key = ImageKey {
+            path: path.clone(),
+            size: TargetSize::Original,
+            color_profile: None,
+        };
+        let limits = DecodeLimits {
+            max_pixels: 20 * 20,
+            max_memory: 20 * 20 * 4,
+            timeout: Duration::MAX,
+        };
+        assert!(decode(&key, &limits).is_ok());
+
+        let err = decode(
+            &key,
+            &DecodeLimits {
+                max_pixels: 20 * 19,
+                ..limits
+            },
+        )
+        .err()
+        .unwrap();
+        assert!(exceeds_limits(&err));
+        // The decoded image is too big
+        let err = decode(
+            &key,
+            &DecodeLimits {
+                max_memory: 20 * 19 * 4,
+                ..limits
+            },
+        )
+        .err()
+        .unwrap();
+        assert!(exceeds_limits(&err));
+    }
+}
@@ -1,17 +1,22 @@
 use std::{
     cell::RefCell,
-    collections::VecDeque,
+    collections::{HashSet, VecDeque},
     path::{Path, PathBuf},
     rc::Rc,
+    sync::Arc,
 };
 
 use log::warn;
+use serde::{Deserialize, Serialize};
 use smithay_client_toolkit::reexports::client::{protocol::wl_surface::WlSurface, QueueHandle};
 
 use crate::{
     filelist_cache::FilelistCache,
+    image_filter::ImageFilter,
+    image_lists::{ImageList, ImageLists},
+    media_index::MediaEntry,
     wallpaper_groups::{WallpaperGroup, WallpaperGroups},
-    wallpaper_info::{Recursive, Sorting, WallpaperInfo},
+    wallpaper_info::{Recursive, SortKey, Sorting, WallpaperInfo},
     wpaperd::Wpaperd,
 };
 
@@ -34,7 +39,7 @@ impl ImageResult {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Queue {
     buffer: VecDeque<PathBuf>,
     current: usize,
@@ -144,6 +149,119 @@ impl Queue {
     fn len(&self) -> usize {
         self.buffer.len()
     }
+
+    /// Replace the content of the queue, keeping at most `size` images
+    fn restore(&mut self, buffer: Vec<PathBuf>, current: usize) {
+        let skip = buffer.len().saturating_sub(self.size);
+        self.buffer = buffer.into_iter().skip(skip).collect();
+        self.current = current
+            .saturating_sub(skip)
+            .min(self.buffer.len().saturating_sub(1));
+    }
+}
+
+/// Navigation state of an [ImagePicker], saved across restarts.
+/// Only the fields relevant to the current sorting are set.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct ImagePickerState {
+    pub current_image: PathBuf,
+    /// The current image has been set via `wpaperctl set`
+    #[serde(default)]
+    pub forced: bool,
+    /// Index of the current image for ascending/descending sorting, or the number of images
+    /// already shown for shuffle sorting
+    #[serde(default)]
+    pub index: Option<usize>,
+    /// Images drawn when using random sorting
+    #[serde(default)]
+    pub queue: Vec<PathBuf>,
+    #[serde(default)]
+    pub queue_current: usize,
+    /// Permutation used by shuffle sorting
+    #[serde(default)]
+    pub shuffle: Vec<PathBuf>,
+}
+
+/// A random permutation of the files in a directory. Every image is shown once before the
+/// permutation gets reshuffled.
+#[derive(Debug, Default, Clone)]
+pub struct Shuffle {
+    order: Vec<PathBuf>,
+    /// Number of images of `order` that have been shown in the current cycle.
+    /// The current image is `order[shown - 1]`
+    shown: usize,
+    /// The filelist `order` has been built from
+    files: Arc<Vec<PathBuf>>,
+}
+
+impl Shuffle {
+    /// Keep the permutation in sync with the files in the directory.
+    /// Removed files are dropped from the permutation, while new files are inserted at a random
+    /// position among the images that have not been shown yet.
+    fn sync(&mut self, files: &Arc<Vec<PathBuf>>, current_image: &Path) {
+        if Arc::ptr_eq(&self.files, files) {
+            return;
+        }
+        self.files = files.clone();
+
+        if self.order.is_empty() {
+            self.order = files.to_vec();
+            fastrand::shuffle(&mut self.order);
+            self.shown = 0;
+            // The image currently displayed counts as shown
+            if let Some(index) = self.order.iter().position(|p| p == current_image) {
+                self.order.swap(0, index);
+                self.shown = 1;
+            }
+            return;
+        }
+
+        let available: HashSet<&PathBuf> = files.iter().collect();
+        let mut shown = self.shown;
+        let mut index = 0;
+        self.order.retain(|path| {
+            let keep = available.contains(path);
+            if !keep && index < self.shown {
+                shown -= 1;
+            }
+            index += 1;
+            keep
+        });
+        self.shown = shown;
+
+        let known: HashSet<PathBuf> = self.order.iter().cloned().collect();
+        for path in files.iter().filter(|path| !known.contains(*path)) {
+            let index = fastrand::usize(self.shown..=self.order.len());
+            self.order.insert(index, path.to_path_buf());
+        }
+    }
+
+    fn next(&mut self, current_image: &Path) -> (usize, PathBuf) {
+        if self.shown >= self.order.len() {
+            // Every image has been shown, start a new cycle
+            fastrand::shuffle(&mut self.order);
+            // Do not show the same image twice in a row
+            if self.order.len() > 1 && self.order[0] == current_image {
+                let last = self.order.len() - 1;
+                self.order.swap(0, last);
+            }
+            self.shown = 0;
+        }
+        (self.shown + 1, self.order[self.shown].to_path_buf())
+    }
+
+    fn previous(&self) -> (usize, PathBuf) {
+        if self.shown >= 2 {
+            (self.shown - 1, self.order[self.shown - 2].to_path_buf())
+        } else {
+            // Wrap around to the last image of the permutation
+            (
+                self.order.len(),
+                self.order[self.order.len() - 1].to_path_buf(),
+            )
+        }
+    }
 }
 
 enum ImagePickerAction {
@@ -151,30 +269,31 @@ enum ImagePickerAction {
     Previous,
 }
 
-struct GroupedRandom {
+/// Membership of a display in a [WallpaperGroup]
+struct GroupMember {
     surface: WlSurface,
     group: Rc<RefCell<WallpaperGroup>>,
     groups: Rc<RefCell<WallpaperGroups>>,
 }
 
-impl GroupedRandom {
+impl GroupMember {
     fn new(
         groups: Rc<RefCell<WallpaperGroups>>,
         group: u8,
         wl_surface: &WlSurface,
-        queue_size: usize,
+        sorting: impl FnOnce() -> ImagePickerSorting,
     ) -> Self {
         Self {
             surface: wl_surface.clone(),
             group: groups
                 .borrow_mut()
-                .get_or_insert(group, wl_surface, queue_size),
+                .get_or_insert(group, wl_surface, sorting),
             groups: groups.clone(),
         }
     }
 }
 
-impl Drop for GroupedRandom {
+impl Drop for GroupMember {
     fn drop(&mut self) {
         let group = self.group.borrow();
         let group_index = group.group;
@@ -183,44 +302,23 @@ impl Drop for GroupedRandom {
     }
 }
 
-enum ImagePickerSorting {
+#[derive(Debug, Clone)]
+pub enum ImagePickerSorting {
     Random(Queue),
-    GroupedRandom(GroupedRandom),
     Ascending(usize),
     Descending(usize),
+    Shuffle(Shuffle),
 }
 
 impl ImagePickerSorting {
-    fn new(
-        wallpaper_info: &WallpaperInfo,
-        wl_surface: &WlSurface,
-        groups: Rc<RefCell<WallpaperGroups>>,
-        filelist_cache: Rc<RefCell<FilelistCache>>,
-    ) -> Self {
+    fn new(wallpaper_info: &WallpaperInfo) -> Self {
         match wallpaper_info.sorting {
             None | Some(Sorting::Random) => {
                 Self::new_random(wallpaper_info.drawn_images_queue_size)
             }
-            Some(Sorting::GroupedRandom { group }) => {
-                ImagePickerSorting::GroupedRandom(GroupedRandom::new(
-                    groups,
-                    group,
-                    wl_surface,
-                    wallpaper_info.drawn_images_queue_size,
-                ))
-            }
-            Some(Sorting::Ascending) => {
-                let files_len = filelist_cache
-                    .clone()
-                    .borrow()
-                    .get(
-                        &wallpaper_info.path,
-                        wallpaper_info.recursive.unwrap_or_default(),
-                    )
-                    .len();
-                Self::new_ascending(files_len)
-            }
+            Some(Sorting::Ascending) => Self::new_ascending(),
             Some(Sorting::Descending) => Self::new_descending(),
+            Some(Sorting::Shuffle) => Self::new_shuffle(),
         }
     }
 
@@ -232,21 +330,60 @@ impl ImagePickerSorting {
         Self::Descending(0)
     }
 
-    fn new_ascending(files_len: usize) -> ImagePickerSorting {
-        Self::Ascending(files_len.saturating_sub(1))
+    fn new_ascending() -> ImagePickerSorting {
+        // The directory might not have been read yet, start right before the first image
+        Self::Ascending(usize::MAX)
+    }
+
+    fn new_shuffle() -> ImagePickerSorting {
+        Self::Shuffle(Shuffle::default())
     }
 }
 
+/// The next image, picked in advance so that it can be decoded before being shown
+#[derive(Debug)]
+pub struct Upcoming {
+    /// The image shown when it has been picked
+    current: PathBuf,
+    /// The files it has been picked from
+    files: Arc<Vec<PathBuf>>,
+    /// The sorting after picking the image, it replaces the current one when the image is shown
+    sorting: ImagePickerSorting,
+    index: Option<usize>,
+    path: PathBuf,
+}
+
+/// The files that passed an [ImageFilter], cached until the filelist or its dimensions change
+struct FilteredFiles {
+    source: Arc<Vec<PathBuf>>,
+    dimensions_generation: u64,
+    filter: ImageFilter,
+    files: Arc<Vec<PathBuf>>,
+    /// Number of files excluded by the filter
+    excluded: usize,
+}
+
 pub struct ImagePicker {
     current_img: PathBuf,
     actions: VecDeque<ImagePickerAction>,
-    sorting: ImagePickerSorting,
+    /// Shared with the other displays of the group, if any
+    sorting: Rc<RefCell<ImagePickerSorting>>,
+    /// The image picked by [ImagePicker::prepare_next_image], shared like the sorting
+    upcoming: Rc<RefCell<Option<Upcoming>>>,
+    group: Option<GroupMember>,
+    /// Order of the images for ascending and descending sorting
+    sort_key: SortKey,
+    /// How many times a favorite image is more likely to be picked by random sorting
+    favorites_weight: u32,
     filelist_cache: Rc<RefCell<FilelistCache>>,
     reload: bool,
     /// Forced image path from `wpaperctl set`, consumed on next get_image_from_path call
     forced_image: Option<PathBuf>,
     /// True if the currently displayed image was set via `wpaperctl set`
     was_last_forced: bool,
+    /// Image restored from the previous run, consumed on the first get_image_from_path call
+    restored_image: Option<PathBuf>,
+    filtered: Option<FilteredFiles>,
 }
 
 impl ImagePicker {
@@ -257,25 +394,93 @@ impl ImagePicker {
         filelist_cache: Rc<RefCell<FilelistCache>>,
         groups: Rc<RefCell<WallpaperGroups>>,
     ) -> Self {
+        let sorting = || ImagePickerSorting::new(wallpaper_info);
+        let (group, sorting, upcoming) = match wallpaper_info.group {
+            Some(group) => {
+                let member = GroupMember::new(groups, group, wl_surface, sorting);
+                let wp_group = member.group.borrow();
+                let (sorting, upcoming) = (wp_group.sorting.clone(), wp_group.upcoming.clone());
+                drop(wp_group);
+                (Some(member), sorting, upcoming)
+            }
+            None => (
+                None,
+                Rc::new(RefCell::new(sorting())),
+                Rc::new(RefCell::new(None)),
+            ),
+        };
         Self {
             current_img: PathBuf::from(""),
             actions: VecDeque::from([ImagePickerAction::Next]),
-            sorting: ImagePickerSorting::new(
-                wallpaper_info,
-                wl_surface,
-                groups,
-                filelist_cache.clone(),
-            ),
+            sorting,
+            upcoming,
+            group,
+            sort_key: wallpaper_info.sort_by,
+            favorites_weight: wallpaper_info.favorites_weight,
             filelist_cache,
             reload: false,
             forced_image: None,
             was_last_forced: false,
+            restored_image: None,
+            filtered: None,
+        }
+    }
+
+    /// Get the next image, the displays in a group follow the one picking it first
+    fn get_image_path(&mut self, files: &Arc<Vec<PathBuf>>) -> (Option<usize>, PathBuf) {
+        let Some(group) = self.group.as_ref().map(|member| member.group.clone()) else {
+            return self.pick_image(files);
+        };
+
+        let wp_group = group.borrow();
+        // Another display of the group already picked the next image
+        if let Some(loading_image) = &wp_group.loading_image {
+            return (None, loading_image.to_path_buf());
+        }
+        // This display is not showing the image of the group yet
+        if wp_group.current_image != self.current_img {
+            return (None, wp_group.current_image.clone());
+        }
+        drop(wp_group);
+
+        let (index, path) = self.pick_image(files);
+        let mut wp_group = group.borrow_mut();
+        if path != wp_group.current_image {
+            wp_group.loading_image = Some(path.to_path_buf());
         }
+        (index, path)
     }
 
     /// Get the next image based on the sorting method
-    fn get_image_path(&mut self, files: &[PathBuf]) -> (Option<usize>, PathBuf) {
-        match (self.actions.front(), &mut self.sorting) {
+    fn pick_image(&mut self, files: &Arc<Vec<PathBuf>>) -> (Option<usize>, PathBuf) {
+        // Use the image picked in advance, if nothing changed in the meantime
+        if matches!(self.actions.front(), Some(ImagePickerAction::Next)) {
+            if let Some(upcoming) = self.upcoming.borrow_mut().take() {
+                if upcoming.current == self.current_img && Arc::ptr_eq(&upcoming.files, files) {
+                    *self.sorting.borrow_mut() = upcoming.sorting;
+                    return (upcoming.index, upcoming.path);
+                }
+            }
+        }
+
+        let mut sorting = self.sorting.borrow_mut();
+        self.pick_with(files, self.actions.front(), &mut sorting)
+    }
+
+    /// Get the image reached with `action` from the state in `sorting`
+    fn pick_with(
+        &self,
+        files: &Arc<Vec<PathBuf>>,
+        action: Option<&ImagePickerAction>,
+        sorting: &mut ImagePickerSorting,
+    ) -> (Option<usize>, PathBuf) {
+        if let ImagePickerSorting::Shuffle(shuffle) = sorting {
+            shuffle.sync(files, &self.current_img);
+        }
+        let filelist_cache = self.filelist_cache.borrow();
+        let lists = filelist_cache.lists();
+
+        match (action, sorting) {
             (
                 None,
                 ImagePickerSorting::Ascending(current_index)
@@ -283,55 +488,38 @@ impl ImagePicker {
             ) if self.current_img.exists() => {
                 (Some(*current_index), self.current_img.to_path_buf())
             }
-            (_, ImagePickerSorting::GroupedRandom(group))
-                if group.group.borrow().loading_image.is_some() =>
-            {
-                let group = group.group.borrow();
-                let loading_image = group.loading_image.as_ref().unwrap();
-                (None, loading_image.to_path_buf())
-            }
-            (_, ImagePickerSorting::GroupedRandom(group))
-                if group.group.borrow().current_image != self.current_img =>
-            {
-                let group = group.group.borrow();
-                (None, group.current_image.clone())
-            }
-            (None, ImagePickerSorting::Random(_) | ImagePickerSorting::GroupedRandom(_))
+            (None, ImagePickerSorting::Random(_) | ImagePickerSorting::Shuffle(_))
                 if self.current_img.exists() =>
             {
                 (None, self.current_img.to_path_buf())
             }
-            (None | Some(ImagePickerAction::Next), ImagePickerSorting::Random(queue)) => {
-                (None, next_random_image(&self.current_img, queue, files))
+            (None | Some(ImagePickerAction::Next), ImagePickerSorting::Shuffle(shuffle)) => {
+                let (index, path) = shuffle.next(&self.current_img);
+                (Some(index), path)
             }
-            (None | Some(ImagePickerAction::Next), ImagePickerSorting::GroupedRandom(group)) => {
-                let mut group = group.group.borrow_mut();
-                if self.current_img == group.current_image {
-                    // start loading a new image
-                    let path = next_random_image(&self.current_img, &mut group.queue, files);
-                    group.loading_image = Some(path.to_path_buf());
-                    (None, path)
-                } else {
-                    (None, group.current_image.clone())
-                }
+            (Some(ImagePickerAction::Previous), ImagePickerSorting::Shuffle(shuffle)) => {
+                let (index, path) = shuffle.previous();
+                (Some(index), path)
             }
-            (Some(ImagePickerAction::Previous), ImagePickerSorting::Random(queue)) => (
+            (None | Some(ImagePickerAction::Next), ImagePickerSorting::Random(queue)) => (
                 None,
-                get_previous_image_for_random(&self.current_img, queue, files.len() <= queue.len()),
+                next_random_image(
+                    &self.current_img,
+                    queue,
+                    files,
+                    lists,
+                    self.favorites_weight,
+                ),
             ),
-            (Some(ImagePickerAction::Previous), ImagePickerSorting::GroupedRandom(group)) => {
-                let mut group = group.group.borrow_mut();
-                let queue = &mut group.queue;
-                let path = get_previous_image_for_random(
+            (Some(ImagePickerAction::Previous), ImagePickerSorting::Random(queue)) => (
+                None,
+                get_previous_image_for_random(
                     &self.current_img,
                     queue,
                     files.len() <= queue.len(),
-                );
-                if path != group.current_image {
-                    group.loading_image = Some(path.to_path_buf());
-                }
-                (None, path)
-            }
+                    lists,
+                ),
+            ),
             (
                 None | Some(ImagePickerAction::Next),
                 ImagePickerSorting::Descending(current_index),
@@ -342,9 +530,9 @@ impl ImagePicker {
                 } else {
                     // if the current img doesn't correspond to the index we have
                     // try looking for it in files
-                    match files.binary_search(&self.current_img) {
-                        Ok(new_index) => new_index,
-                        Err(_err) => {
+                    match files.iter().position(|file| file == &self.current_img) {
+                        Some(new_index) => new_index,
+                        None => {
                             // if we don't find it, use the last index as starting point
                             // if the current_index is too big, start from last image
                             // this is a fail safe in case many files gets deleted
@@ -371,27 +559,158 @@ impl ImagePicker {
                 let index = if files.get(*current_index) == Some(&self.current_img) {
                     *current_index
                 } else {
-                    match files.binary_search(&self.current_img) {
-                        Ok(new_index) => new_index,
-                        Err(_err) => *current_index,
-                    }
+                    files
+                        .iter()
+                        .position(|file| file == &self.current_img)
+                        .unwrap_or(*current_index)
                 };
-                let index = (index + 1) % files.len();
+                let index = index.wrapping_add(1) % files.len();
                 (Some(index), files[index].to_path_buf())
             }
         }
     }
 
+    /// Pick the image that the next call to [ImagePicker::next_image] will show, so that it can
+    /// be decoded in advance. Return None when it can't be known yet.
+    pub fn prepare_next_image(
+        &mut self,
+        path: &Path,
+        recursive: &Option<Recursive>,
+        filter: &ImageFilter,
+    ) -> Option<PathBuf> {
+        if self.forced_image.is_some() || !self.actions.is_empty() || self.reload || !path.is_dir()
+        {
+            return None;
+        }
+        if let Some(member) = &self.group {
+            let wp_group = member.group.borrow();
+            // This display is following the others, only the one picking the image knows it
+            if wp_group.loading_image.is_some() || wp_group.current_image != self.current_img {
+                return None;
+            }
+        }
+
+        let files = self.filelist_cache.borrow().get(
+            path,
+            recursive.unwrap_or_default(),
+            self.files_sort_key(),
+        );
+        let files = self.filter_files(files, filter);
+        if files.is_empty() {
+            return None;
+        }
+        if let Some(upcoming) = &*self.upcoming.borrow() {
+            if upcoming.current == self.current_img && Arc::ptr_eq(&upcoming.files, &files) {
+                return Some(upcoming.path.clone());
+            }
+        }
+
+        // Pick it from a copy of the sorting, the real one is only updated when it gets shown
+        let mut sorting = self.sorting.borrow().clone();
+        let (index, next) = self.pick_with(&files, Some(&ImagePickerAction::Next), &mut sorting);
+        if next == self.current_img {
+            return None;
+        }
+        *self.upcoming.borrow_mut() = Some(Upcoming {
+            current: self.current_img.clone(),
+            files,
+            sorting,
+            index,
+            path: next.clone(),
+        });
+        Some(next)
+    }
+
     /// Set the wallpaper to a specific image path.
     /// The forced image will be returned by the next call to `get_image_from_path`.
     pub fn set_image(&mut self, path: PathBuf) {
         self.forced_image = Some(path);
+        // It replaces a forced background, if any
+        self.was_last_forced = false;
+    }
+
+    /// Something other than an image is shown, until the next or previous image is requested
+    pub fn start_detour(&mut self) {
+        self.was_last_forced = true;
+    }
+
+    /// The detour started by [ImagePicker::start_detour] or by a forced image has ended
+    pub fn detour_ended(&self) -> bool {
+        !self.was_last_forced
+    }
+
+    /// `path` could not be decoded, don't pick it again until it gets modified
+    pub fn image_failed(&self, path: PathBuf) {
+        self.filelist_cache.borrow_mut().mark_failed(path);
+    }
+
+    pub fn image_decoded(&self, path: &Path, average_color: [u8; 3], entry: MediaEntry) {
+        self.filelist_cache
+            .borrow_mut()
+            .image_decoded(path, average_color, entry);
+    }
+
+    /// Return the files matching `filter`, or all of them if none does
+    fn filter_files(
+        &mut self,
+        files: Arc<Vec<PathBuf>>,
+        filter: &ImageFilter,
+    ) -> Arc<Vec<PathBuf>> {
+        if filter.is_empty() {
+            self.filtered = None;
+            return files;
+        }
+
+        let filelist_cache = self.filelist_cache.borrow();
+        let dimensions_generation = filelist_cache.dimensions_generation();
+        if let Some(filtered) = &self.filtered {
+            if Arc::ptr_eq(&filtered.source, &files)
+                && filtered.dimensions_generation == dimensions_generation
+                && filtered.filter == *filter
+            {
+                return filtered.files.clone();
+            }
+        }
+
+        // Images whose header has not been read yet are excluded
+        let matching: Vec<PathBuf> = files
+            .iter()
+            .filter(|file| {
+                filelist_cache
+                    .dimensions(file)
+                    .is_some_and(|dimensions| filter.matches(dimensions))
+            })
+            .cloned()
+            .collect();
+        drop(filelist_cache);
+        let filtered_files = if matching.is_empty() {
+            files.clone()
+        } else {
+            Arc::new(matching)
+        };
+        self.filtered = Some(FilteredFiles {
+            excluded: files.len() - filtered_files.len(),
+            source: files,
+            dimensions_generation,
+            filter: *filter,
+            files: filtered_files.clone(),
+        });
+        filtered_files
+    }
+
+    /// Number of images excluded by the filter the last time an image has been picked
+    pub fn excluded_images(&self) -> usize {
+        self.filtered
+            .as_ref()
+            .map(|filtered| filtered.excluded)
+            .unwrap_or_default()
     }
 
     pub fn get_image_from_path(
         &mut self,
         path: &Path,
         recursive: &Option<Recursive>,
+        filter: &ImageFilter,
     ) -> Option<ImageResult> {
         // Check for forced image first (from wpaperctl set)
         // Don't update navigation state - forced images are "detours"
@@ -404,15 +723,35 @@ impl ImagePicker {
         self.was_last_forced = false;
 
         if path.is_dir() {
-            let files = self
-                .filelist_cache
-                .borrow()
-                .get(path, recursive.unwrap_or_default());
+            let files = self.filelist_cache.borrow().get(
+                path,
+                recursive.unwrap_or_default(),
+                self.files_sort_key(),
+            );
+            let files = self.filter_files(files, filter);
 
             // There are no images, forcefully break out of the loop
             if files.is_empty() {
-                warn!("Directory {path:?} does not contain any valid image files.");
+                // The images will be picked once the directory has been read
+                if !self
+                    .filelist_cache
+                    .borrow()
+                    .is_scanning(path, recursive.unwrap_or_default())
+                {
+                    warn!("Directory {path:?} does not contain any valid image files.");
+                }
                 None
+            } else if let Some(img_path) = self
+                .restored_image
+                .take()
+                .filter(|img_path| files.contains(img_path))
+            {
+                // Show the image from the previous run instead of picking a new one
+                self.actions.clear();
+                Some(ImageResult::FromList {
+                    path: img_path,
+                    index: self.sorting_index(),
+                })
             } else {
                 let (index, img_path) = self.get_image_path(&files);
                 if img_path == self.current_img && !self.reload {
@@ -447,7 +786,12 @@ impl ImagePicker {
                 path: img_path,
                 index,
             } => {
-                match (self.actions.pop_front(), &mut self.sorting) {
+                if let Some(member) = &self.group {
+                    let mut group = member.group.borrow_mut();
+                    group.loading_image = None;
+                    group.current_image.clone_from(&img_path);
+                }
+                match (self.actions.pop_front(), &mut *self.sorting.borrow_mut()) {
                     (Some(ImagePickerAction::Next), ImagePickerSorting::Random(queue)) => {
                         queue.push(img_path.clone());
                     }
@@ -455,25 +799,21 @@ impl ImagePicker {
                         None | Some(ImagePickerAction::Previous),
                         ImagePickerSorting::Random { .. },
                     ) => {}
-                    (
-                        None | Some(ImagePickerAction::Previous),
-                        ImagePickerSorting::GroupedRandom(group),
-                    ) => {
-                        let mut group = group.group.borrow_mut();
-                        group.loading_image = None;
-                        group.current_image.clone_from(&img_path);
-                    }
+                    // The other displays of the group follow without an index, keep the
+                    // one set by the display that picked the image
                     (
                         _,
                         ImagePickerSorting::Ascending(current_index)
                         | ImagePickerSorting::Descending(current_index),
-                    ) => *current_index = index.unwrap_or(usize::MAX),
-                    (Some(ImagePickerAction::Next), ImagePickerSorting::GroupedRandom(group)) => {
-                        let mut group = group.group.borrow_mut();
-                        let queue = &mut group.queue;
-                        queue.push(img_path.clone());
-                        group.loading_image = None;
-                        group.current_image.clone_from(&img_path);
+                    ) => {
+                        if let Some(index) = index {
+                            *current_index = index;
+                        }
+                    }
+                    (_, ImagePickerSorting::Shuffle(shuffle)) => {
+                        if let Some(index) = index {
+                            shuffle.shown = index;
+                        }
                     }
                 }
                 self.current_img = img_path;
@@ -510,6 +850,8 @@ impl ImagePicker {
         } else {
             self.actions.push_back(ImagePickerAction::Previous);
         }
+        // The image picked in advance was the next one
+        self.upcoming.borrow_mut().take();
     }
 
     /// Update wallpaper by going up 1 index through the cached image paths
@@ -523,6 +865,84 @@ impl ImagePicker {
         self.current_img.clone()
     }
 
+    /// The order of the files only matters for ascending and descending sorting
+    fn files_sort_key(&self) -> SortKey {
+        match &*self.sorting.borrow() {
+            ImagePickerSorting::Ascending(_) | ImagePickerSorting::Descending(_) => self.sort_key,
+            ImagePickerSorting::Random(_) | ImagePickerSorting::Shuffle(_) => SortKey::Name,
+        }
+    }
+
+    fn sorting_index(&self) -> Option<usize> {
+        match &*self.sorting.borrow() {
+            ImagePickerSorting::Random(_) => None,
+            ImagePickerSorting::Ascending(index) | ImagePickerSorting::Descending(index) => {
+                Some(*index)
+            }
+            ImagePickerSorting::Shuffle(shuffle) => Some(shuffle.shown),
+        }
+    }
+
+    /// Return the navigation state, so that it can be restored with [ImagePicker::restore_state]
+    pub fn state(&self) -> ImagePickerState {
+        let mut state = ImagePickerState {
+            current_image: self.current_img.clone(),
+            forced: self.was_last_forced,
+            index: self.sorting_index(),
+            ..Default::default()
+        };
+        match &*self.sorting.borrow() {
+            ImagePickerSorting::Random(queue) => {
+                state.queue = queue.buffer.iter().cloned().collect();
+                state.queue_current = queue.current;
+            }
+            ImagePickerSorting::Ascending(_) | ImagePickerSorting::Descending(_) => {}
+            ImagePickerSorting::Shuffle(shuffle) => {
+                state.shuffle.clone_from(&shuffle.order);
+            }
+        }
+        state
+    }
+
+    /// Restore the state saved by a previous run. The saved image will be shown
+    /// instead of picking a new one, if it is still available.
+    pub fn restore_state(&mut self, state: ImagePickerState) {
+        // Another display of the same group might have already restored it, a display connected
+        // again later follows the group instead of going back to its own state
+        if let Some(member) = &self.group {
+            let mut group = member.group.borrow_mut();
+            if group.restored {
+                return;
+            }
+            group.restored = true;
+        }
+        match &mut *self.sorting.borrow_mut() {
+            ImagePickerSorting::Random(queue) => {
+                queue.restore(state.queue, state.queue_current);
+            }
+            ImagePickerSorting::Ascending(index) | ImagePickerSorting::Descending(index) => {
+                if let Some(restored_index) = state.index {
+                    *index = restored_index;
+                }
+            }
+            ImagePickerSorting::Shuffle(shuffle) => {
+                shuffle.order = state.shuffle;
+                shuffle.shown = state.index.unwrap_or_default().min(shuffle.order.len());
+            }
+        }
+
+        if state.current_image.as_os_str().is_empty() {
+            return;
+        }
+        if state.forced {
+            if state.current_image.is_file() {
+                self.set_image(state.current_image);
+            }
+        } else {
+            self.restored_image = Some(state.current_image);
+        }
+    }
+
     /// Return true if the path changed
     pub fn update_sorting(
         &mut self,
@@ -531,105 +951,93 @@ impl ImagePicker {
         path_changed: bool,
         wallpaper_groups: &Rc<RefCell<WallpaperGroups>>,
     ) {
-        if let Some(new_sorting) = wallpaper_info.sorting {
-            match (&mut self.sorting, new_sorting) {
-                // If the the sorting stayed the same, do nothing
-                (ImagePickerSorting::Ascending(_), Sorting::Ascending)
-                | (ImagePickerSorting::Descending(_), Sorting::Descending)
-                | (ImagePickerSorting::Random(_), Sorting::Random)
-                    if !path_changed => {}
-                (_, Sorting::Ascending) if path_changed => {
-                    self.sorting = ImagePickerSorting::new_ascending(
-                        self.filelist_cache
-                            .borrow()
-                            .get(
-                                &wallpaper_info.path,
-                                wallpaper_info.recursive.unwrap_or_default(),
-                            )
-                            .len(),
-                    );
-                }
-                (_, Sorting::Descending) if path_changed => {
-                    self.sorting = ImagePickerSorting::new_descending();
-                }
-                (_, Sorting::Ascending | Sorting::Descending) => {
-                    let index = self.get_current_index();
-                    self.sorting = match new_sorting {
-                        Sorting::Random | Sorting::GroupedRandom { .. } => unreachable!(),
-                        Sorting::Ascending => ImagePickerSorting::Ascending(index),
-                        Sorting::Descending => ImagePickerSorting::Descending(index),
-                    };
-                }
-                // The path has changed, use a new random sorting, otherwise we reuse the current
-                // drawn_images
-                (_, Sorting::Random) if path_changed => {
-                    self.sorting =
-                        ImagePickerSorting::new_random(wallpaper_info.drawn_images_queue_size);
-                }
-                (_, Sorting::Random) => {
-                    // if the path was not changed, use the current image as the first image of
-                    // the drawn_images
-                    let mut queue = Queue::with_capacity(wallpaper_info.drawn_images_queue_size);
-                    queue.push(self.current_image());
-                    self.sorting = ImagePickerSorting::Random(queue);
-                }
-                (_, Sorting::GroupedRandom { group }) if path_changed => {
-                    self.sorting = ImagePickerSorting::GroupedRandom(GroupedRandom::new(
-                        wallpaper_groups.clone(),
-                        group,
-                        wl_surface,
-                        wallpaper_info.drawn_images_queue_size,
-                    ));
-                }
-                // If the group is the same
-                (
-                    ImagePickerSorting::GroupedRandom(grouped_random),
-                    Sorting::GroupedRandom { group },
-                ) if grouped_random.group.borrow().group == group => {}
-                (_, Sorting::GroupedRandom { group }) => {
-                    let grouped_random = GroupedRandom::new(
-                        wallpaper_groups.clone(),
-                        group,
-                        wl_surface,
-                        wallpaper_info.drawn_images_queue_size,
-                    );
-
-                    let mut group = grouped_random.group.borrow_mut();
+        // The index will be looked up again in the new order by get_image_path
+        self.sort_key = wallpaper_info.sort_by;
+        self.favorites_weight = wallpaper_info.favorites_weight;
+
+        let current_group = self
+            .group
+            .as_ref()
+            .map(|member| member.group.borrow().group);
+        if current_group != wallpaper_info.group {
+            // Leave the previous group, keeping a copy of its sorting
+            self.group = None;
+            let sorting = self.sorting.borrow().clone();
+            (self.sorting, self.upcoming) = match wallpaper_info.group {
+                Some(group) => {
+                    let member =
+                        GroupMember::new(wallpaper_groups.clone(), group, wl_surface, || sorting);
+                    let mut wp_group = member.group.borrow_mut();
                     // If there are no other surfaces, we must reuse the current wallpaper
-                    if group.surfaces.len() == 1 {
-                        group.current_image = self.current_img.clone();
-                        group.queue.push(self.current_img.clone());
+                    if wp_group.surfaces.len() == 1 {
+                        wp_group.current_image = self.current_img.clone();
                     }
-
-                    drop(group);
-                    self.sorting = ImagePickerSorting::GroupedRandom(grouped_random);
+                    let shared = (wp_group.sorting.clone(), wp_group.upcoming.clone());
+                    drop(wp_group);
+                    self.group = Some(member);
+                    shared
                 }
-            }
-        } else {
-            self.sorting = ImagePickerSorting::new_random(wallpaper_info.drawn_images_queue_size);
+                None => (Rc::new(RefCell::new(sorting)), Rc::new(RefCell::new(None))),
+            };
         }
-    }
-
-    fn get_current_index(&mut self) -> usize {
-        match &self.sorting {
-            ImagePickerSorting::Random(_) | ImagePickerSorting::GroupedRandom(_) => unreachable!(),
-            ImagePickerSorting::Ascending(index) | ImagePickerSorting::Descending(index) => *index,
+        // The next image might be different with the new settings
+        self.upcoming.borrow_mut().take();
+
+        // The displays of a group share the sorting, it is only replaced by the first one
+        let mut sorting = self.sorting.borrow_mut();
+        match (&*sorting, wallpaper_info.sorting.unwrap_or_default()) {
+            // If the the sorting stayed the same, do nothing
+            (ImagePickerSorting::Ascending(_), Sorting::Ascending)
+            | (ImagePickerSorting::Descending(_), Sorting::Descending)
+            | (ImagePickerSorting::Random(_), Sorting::Random)
+            | (ImagePickerSorting::Shuffle(_), Sorting::Shuffle)
+                if !path_changed => {}
+            (_, Sorting::Ascending) if path_changed => {
+                *sorting = ImagePickerSorting::new_ascending();
+            }
+            (_, Sorting::Descending) if path_changed => {
+                *sorting = ImagePickerSorting::new_descending();
+            }
+            (_, new_sorting @ (Sorting::Ascending | Sorting::Descending)) => {
+                // The index is looked up from the current image when it doesn't match
+                let index = match &*sorting {
+                    ImagePickerSorting::Ascending(index)
+                    | ImagePickerSorting::Descending(index) => *index,
+                    ImagePickerSorting::Random(_) | ImagePickerSorting::Shuffle(_) => 0,
+                };
+                *sorting = match new_sorting {
+                    Sorting::Random | Sorting::Shuffle => unreachable!(),
+                    Sorting::Ascending => ImagePickerSorting::Ascending(index),
+                    Sorting::Descending => ImagePickerSorting::Descending(index),
+                };
+            }
+            // The path has changed, use a new random sorting, otherwise we reuse the current
+            // drawn_images
+            (_, Sorting::Random) if path_changed => {
+                *sorting = ImagePickerSorting::new_random(wallpaper_info.drawn_images_queue_size);
+            }
+            (_, Sorting::Random) => {
+                // if the path was not changed, use the current image as the first image of
+                // the drawn_images
+                let mut queue = Queue::with_capacity(wallpaper_info.drawn_images_queue_size);
+                queue.push(self.current_image());
+                *sorting = ImagePickerSorting::Random(queue);
+            }
+            // The current image will be marked as shown when the permutation is built
+            (_, Sorting::Shuffle) => {
+                *sorting = ImagePickerSorting::new_shuffle();
+            }
         }
     }
 
     pub fn update_queue_size(&mut self, drawn_images_queue_size: usize) {
-        match &mut self.sorting {
+        match &mut *self.sorting.borrow_mut() {
             ImagePickerSorting::Random(queue) => {
                 queue.resize(drawn_images_queue_size);
             }
-            ImagePickerSorting::Ascending(_) | ImagePickerSorting::Descending(_) => {}
-            ImagePickerSorting::GroupedRandom(group) => {
-                group
-                    .group
-                    .borrow_mut()
-                    .queue
-                    .resize(drawn_images_queue_size);
-            }
+            ImagePickerSorting::Ascending(_)
+            | ImagePickerSorting::Descending(_)
+            | ImagePickerSorting::Shuffle(_) => {}
         }
     }
 
@@ -649,8 +1057,8 @@ impl ImagePicker {
     }
 
     pub fn handle_grouped_sorting(&self, qh: &QueueHandle<Wpaperd>) {
-        if let ImagePickerSorting::GroupedRandom(grouped_random) = &self.sorting {
-            grouped_random.group.borrow().queue_all_surfaces(qh);
+        if let Some(member) = &self.group {
+            member.group.borrow().queue_all_surfaces(qh);
         }
     }
 
@@ -659,7 +1067,13 @@ impl ImagePicker {
     }
 }
 
-fn next_random_image(current_image: &Path, queue: &mut Queue, files: &[PathBuf]) -> PathBuf {
+fn next_random_image(
+    current_image: &Path,
+    queue: &mut Queue,
+    files: &[PathBuf],
+    lists: &ImageLists,
+    favorites_weight: u32,
+) -> PathBuf {
     // Use the next images in the queue, if any
     let wrap = files.len() <= queue.len();
     // If there is only one image just return it
@@ -668,7 +1082,7 @@ fn next_random_image(current_image: &Path, queue: &mut Queue, files: &[PathBuf])
     }
 
     while let Some(next) = queue.next(wrap) {
-        if next.exists() {
+        if next.exists() && !lists.is_excluded(next) {
             return next.to_path_buf();
         }
     }
@@ -678,7 +1092,7 @@ fn next_random_image(current_image: &Path, queue: &mut Queue, files: &[PathBuf])
     // that the queue is bigger than the amount of available wallpapers
     let mut tries = 5;
     loop {
-        let index = fastrand::usize(..files.len());
+        let index = random_index(files, &lists.favorites, favorites_weight);
         // search for an image that has not been drawn yet
         // fail after 5 tries
         if !queue.contains(&files[index]) {
@@ -701,9 +1115,39 @@ fn next_random_image(current_image: &Path, queue: &mut Queue, files: &[PathBuf])
     }
 }
 
-fn get_previous_image_for_random(current_image: &Path, queue: &mut Queue, wrap: bool) -> PathBuf {
+/// Pick a random image in `files`, favorites are `favorites_weight` times more likely to be picked
+fn random_index(files: &[PathBuf], favorites: &ImageList, favorites_weight: u32) -> usize {
+    if favorites_weight <= 1 || favorites.is_empty() {
+        return fastrand::usize(..files.len());
+    }
+
+    let weight = |file: &PathBuf| {
+        if favorites.contains(file) {
+            favorites_weight as usize
+        } else {
+            1
+        }
+    };
+    let total: usize = files.iter().map(weight).sum();
+    let mut target = fastrand::usize(..total);
+    for (index, file) in files.iter().enumerate() {
+        let weight = weight(file);
+        if target < weight {
+            return index;
+        }
+        target -= weight;
+    }
+    unreachable!("the target is always smaller than the sum of the weights")
+}
+
+fn get_previous_image_for_random(
+    current_image: &Path,
+    queue: &mut Queue,
+    wrap: bool,
+    lists: &ImageLists,
+) -> PathBuf {
     while let Some(prev) = queue.previous(wrap) {
-        if prev.exists() {
+        if prev.exists() && !lists.is_excluded(prev) {
             return prev.to_path_buf();
         }
     }
@@ -851,6 +1295,111 @@ mod tests {
         assert_eq!(queue.current(), Path::new("/image2.png"));
     }
 
+    #[test]
+    fn test_queue_restore_keeps_last_images() {
+        let mut queue = Queue::with_capacity(2);
+        queue.restore(
+            vec![
+                PathBuf::from("/image1.png"),
+                PathBuf::from("/image2.png"),
+                PathBuf::from("/image3.png"),
+            ],
+            1,
+        );
+
+        // The oldest image doesn't fit anymore, the current one is still image2
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.current(), Path::new("/image2.png"));
+        assert_eq!(queue.next(false), Some(Path::new("/image3.png")));
+    }
+
+    // =======================================================
+    // Tests for Shuffle sorting
+    // =======================================================
+
+    fn filelist(names: &[&str]) -> Arc<Vec<PathBuf>> {
+        Arc::new(names.iter().map(PathBuf::from).collect())
+    }
+
+    #[test]
+    fn test_shuffle_shows_every_image_once() {
+        let files = filelist(&["/1.png", "/2.png", "/3.png", "/4.png", "/5.png"]);
+        let mut shuffle = Shuffle::default();
+        let mut current = PathBuf::new();
+        shuffle.sync(&files, &current);
+
+        let mut shown = HashSet::new();
+        for _ in 0..files.len() {
+            let (index, path) = shuffle.next(&current);
+            shuffle.shown = index;
+            current = path;
+            assert!(shown.insert(current.clone()));
+        }
+        assert_eq!(shown.len(), files.len());
+
+        // A new cycle starts, without repeating the last image
+        let (index, path) = shuffle.next(&current);
+        assert_eq!(index, 1);
+        assert_ne!(path, current);
+    }
+
+    #[test]
+    fn test_shuffle_previous() {
+        let files = filelist(&["/1.png", "/2.png", "/3.png"]);
+        let mut shuffle = Shuffle::default();
+        shuffle.sync(&files, Path::new(""));
+
+        let (index, first) = shuffle.next(Path::new(""));
+        shuffle.shown = index;
+        let (index, second) = shuffle.next(&first);
+        shuffle.shown = index;
+        assert_ne!(first, second);
+
+        let (index, previous) = shuffle.previous();
+        assert_eq!(index, 1);
+        assert_eq!(previous, first);
+    }
+
+    #[test]
+    fn test_shuffle_current_image_counts_as_shown() {
+        let files = filelist(&["/1.png", "/2.png", "/3.png"]);
+        let mut shuffle = Shuffle::default();
+        shuffle.sync(&files, Path::new("/2.png"));
+        assert_eq!(shuffle.shown, 1);
+        assert_eq!(shuffle.order[0], Path::new("/2.png"));
+    }
+
+    #[test]
+    fn test_shuffle_sync_with_filelist_changes() {
+        let files = filelist(&["/1.png", "/2.png", "/3.png", "/4.png"]);
+        let mut shuffle = Shuffle::default();
+        let mut current = PathBuf::new();
+        shuffle.sync(&files, &current);
+        for _ in 0..2 {
+            let (index, path) = shuffle.next(&current);
+            shuffle.shown = index;
+            current = path;
+        }
+        let first = shuffle.order[0].clone();
+        let not_shown = shuffle.order[3].clone();
+
+        // Remove an image already shown and one not shown yet, then add a new one
+        let files = Arc::new(
+            files
+                .iter()
+                .filter(|p| **p != first && **p != not_shown)
+                .cloned()
+                .chain([PathBuf::from("/5.png")])
+                .collect::<Vec<_>>(),
+        );
+        shuffle.sync(&files, &current);
+        assert_eq!(shuffle.order.len(), 3);
+        assert_eq!(shuffle.shown, 1);
+        assert_eq!(shuffle.order[0], current);
+        // The new image has been added among the ones not shown yet
+        assert!(shuffle.order[1..].contains(&PathBuf::from("/5.png")));
+    }
+
     // =======================================================
     // Tests for ImageResult enum
     // =======================================================
@@ -888,6 +1437,47 @@ mod tests {
         }
     }
 
+    // =======================================================
+    // Tests for favorites and banned images
+    // =======================================================
+
+    #[test]
+    fn test_random_index_favors_favorites() {
+        let files = filelist(&["/1.png", "/2.png", "/3.png"]);
+        let mut favorites = ImageList::default();
+        favorites.insert(PathBuf::from("/2.png")).unwrap();
+
+        let picked = (0..1000)
+            .filter(|_| random_index(&files, &favorites, 1000) == 1)
+            .count();
+        // The favorite has a 1000/1002 chance of being picked each time
+        assert!(picked > 900);
+    }
+
+    #[test]
+    fn test_next_random_image_skips_banned_images_in_queue() {
+        // The images in the queue must exist
+        let tmp = tempfile::tempdir().unwrap();
+        let [first, banned, next] = ["1.png", "2.png", "3.png"].map(|name| tmp.path().join(name));
+        for path in [&first, &banned, &next] {
+            std::fs::write(path, "").unwrap();
+        }
+        let files = Arc::new(vec![first.clone(), next.clone()]);
+        let mut lists = ImageLists::default();
+        lists.banned.insert(banned.clone()).unwrap();
+        let mut queue = Queue::with_capacity(5);
+        queue.push(first.clone());
+        queue.push(banned);
+        queue.push(next.clone());
+        queue.previous(false);
+        queue.previous(false);
+
+        assert_eq!(
+            next_random_image(&first, &mut queue, &files, &lists, 1),
+            next
+        );
+    }
+
     // =======================================================
     // Tests for ImagePicker forced image state transitions
     // =======================================================
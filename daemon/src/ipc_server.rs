@@ -10,10 +10,13 @@ use std::path::{Path, PathBuf};
 use color_eyre::eyre::{ensure, WrapErr};
 use color_eyre::{Result, Section};
 use smithay_client_toolkit::reexports::client::QueueHandle;
-use wpaperd_ipc::{IpcError, IpcMessage, IpcResponse};
+use wpaperd_ipc::{DisplayStatus, IpcError, IpcMessage, IpcResponse};
 
+use crate::image_format;
+use crate::render::is_shader;
 use crate::socket::SocketSource;
 use crate::surface::{PauseReason, Surface};
+use crate::wallpaper_info::{Background, Color, Gradient};
 use crate::Wpaperd;
 
 /// Create an IPC socket.
@@ -48,6 +51,26 @@ fn check_monitors(wpaperd: &Wpaperd, monitors: &[String]) -> Result<(), IpcError
     Ok(())
 }
 
+/// Return the current wallpapers of the monitors, without duplicates
+fn current_images(wpaperd: &mut Wpaperd, monitors: Vec<String>) -> Vec<PathBuf> {
+    let mut images: Vec<PathBuf> = collect_surfaces(wpaperd, monitors)
+        .iter()
+        .map(|surface| surface.image_picker.current_image())
+        .filter(|path| !path.as_os_str().is_empty())
+        .collect();
+    images.sort_unstable();
+    images.dedup();
+    images
+}
+
+/// The favorites or banned list has not been saved after changing `path`
+fn save_error(path: PathBuf, err: color_eyre::Report) -> IpcError {
+    IpcError::SaveError {
+        path,
+        error: format!("{err:#}"),
+    }
+}
+
 fn collect_surfaces(wpaperd: &mut Wpaperd, monitors: Vec<String>) -> Vec<&mut Surface> {
     let monitors: HashSet<String> = HashSet::from_iter(monitors);
     if monitors.is_empty() {
@@ -173,15 +196,28 @@ pub fn handle_message(
         }
 
         IpcMessage::GetStatus { monitors } => {
+            let filelist_cache = wpaperd.filelist_cache.clone();
+            let image_loader = wpaperd.image_loader.clone();
             check_monitors(wpaperd, &monitors).map(|_| IpcResponse::DisplaysStatus {
                 entries: collect_surfaces(wpaperd, monitors)
                     .iter()
                     .map(|surface| {
-                        (
-                            surface.name().to_string(),
-                            surface.status().to_string(),
-                            surface.get_remaining_duration(),
-                        )
+                        let image_loader = image_loader.borrow();
+                        let stats = image_loader.stats(surface.name());
+                        DisplayStatus {
+                            display: surface.name().to_string(),
+                            status: surface.status().to_string(),
+                            duration_left: surface.get_remaining_duration(),
+                            excluded_images: surface.image_picker.excluded_images(),
+                            scanning: surface.wallpaper_info.path.is_dir()
+                                && filelist_cache.borrow().is_scanning(
+                                    &surface.wallpaper_info.path,
+                                    surface.wallpaper_info.recursive.unwrap_or_default(),
+                                ),
+                            cache_hits: stats.hits,
+                            cache_misses: stats.misses,
+                            rejected_images: image_loader.rejected(surface.name()),
+                        }
                     })
                     .collect(),
             })
@@ -198,10 +234,7 @@ pub fn handle_message(
                     "Path is not a file: {}",
                     path.display()
                 )))
-            } else if !new_mime_guess::from_path(&path)
-                .first()
-                .is_some_and(|mime| mime.type_() == "image")
-            {
+            } else if !image_format::is_supported(&path) && !is_shader(&path) {
                 Err(IpcError::ValidationError(format!(
                     "Not a supported image format: {}",
                     path.display()
@@ -217,6 +250,87 @@ pub fn handle_message(
                 })
             }
         }
+
+        IpcMessage::SetColor {
+            color,
+            to,
+            angle,
+            monitors,
+        } => {
+            let parse = |color: &str| color.parse::<Color>().map_err(IpcError::ValidationError);
+            let background = parse(&color).and_then(|from| {
+                Ok(match to {
+                    Some(to) => Background::Gradient(Gradient {
+                        from,
+                        to: parse(&to)?,
+                        angle: angle.unwrap_or_else(Gradient::default_angle),
+                    }),
+                    None => Background::Color(from),
+                })
+            });
+            background.and_then(|background| {
+                check_monitors(wpaperd, &monitors).map(|_| {
+                    for surface in collect_surfaces(wpaperd, monitors) {
+                        surface.set_background(background);
+                        surface.pause_for_set();
+                        surface.load_new_wallpaper();
+                        surface.queue_draw(&qh);
+                    }
+                    IpcResponse::Ok
+                })
+            })
+        }
+
+        IpcMessage::Favorite { monitors } => check_monitors(wpaperd, &monitors).and_then(|_| {
+            // Change the images of all the displays, even if the list can't be saved
+            let mut res = Ok(IpcResponse::Ok);
+            for path in current_images(wpaperd, monitors) {
+                if let Err(err) = wpaperd.filelist_cache.borrow_mut().favorite(path.clone()) {
+                    res = Err(save_error(path, err));
+                }
+            }
+            res
+        }),
+
+        IpcMessage::Unfavorite { monitors } => check_monitors(wpaperd, &monitors).and_then(|_| {
+            // Change the images of all the displays, even if the list can't be saved
+            let mut res = Ok(IpcResponse::Ok);
+            for path in current_images(wpaperd, monitors) {
+                if let Err(err) = wpaperd.filelist_cache.borrow_mut().unfavorite(&path) {
+                    res = Err(save_error(path, err));
+                }
+            }
+            res
+        }),
+
+        IpcMessage::Ban { monitors } => check_monitors(wpaperd, &monitors).and_then(|_| {
+            let banned = current_images(wpaperd, monitors);
+            // The images are banned until the next restart even if the list can't be saved
+            let mut res = Ok(IpcResponse::Ok);
+            for path in &banned {
+                if let Err(err) = wpaperd.filelist_cache.borrow_mut().ban(path.clone()) {
+                    res = Err(save_error(path.clone(), err));
+                }
+            }
+            // Replace the banned wallpapers on every display showing them
+            for surface in wpaperd
+                .surfaces
+                .iter_mut()
+                .filter(|surface| banned.contains(&surface.image_picker.current_image()))
+            {
+                if surface.pause_reason() == Some(PauseReason::Set) {
+                    surface.resume();
+                }
+                surface.image_picker.next_image();
+                surface.load_new_wallpaper();
+            }
+            res
+        }),
+
+        IpcMessage::Rescan => {
+            wpaperd.filelist_cache.borrow_mut().rescan();
+            Ok(IpcResponse::Ok)
+        }
     };
 
     let mut stream = BufWriter::new(ustream);
@@ -1,13 +1,23 @@
+mod animation;
+mod color;
 mod config;
 mod display_info;
 mod filelist_cache;
+mod image_cache;
+mod image_filter;
+mod image_format;
+mod image_lists;
 mod image_loader;
 mod image_picker;
 mod ipc_server;
+mod media_index;
 mod opts;
 mod render;
 mod socket;
+mod state;
 mod surface;
+#[cfg(feature = "svg")]
+mod svg;
 mod wallpaper_groups;
 mod wallpaper_info;
 mod wpaperd;
@@ -25,6 +35,7 @@ use std::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
+    time::Duration,
 };
 
 use clap::Parser;
@@ -35,9 +46,11 @@ use egl::API as egl;
 use filelist_cache::FilelistCache;
 use flexi_logger::{Duplicate, FileSpec, Logger};
 use hotwatch::Hotwatch;
-use image_loader::ImageLoader;
+use image_lists::ImageLists;
+use image_loader::{DecodeLimits, ImageLoader};
 use ipc_server::{handle_message, listen_on_ipc_socket};
 use log::error;
+use media_index::MediaIndex;
 use nix::unistd::fork;
 use opts::Opts;
 use smithay_client_toolkit::reexports::{
@@ -140,9 +153,14 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
         .listen_to_changes(&mut hotwatch, ping)
         .wrap_err("Failed to watch on config file changes")?;
 
-    let (ping, filelist_cache) =
-        FilelistCache::new(config.paths(), &mut hotwatch, event_loop.handle())
-            .wrap_err("Failed to create FilelistCache")?;
+    let (ping, filelist_cache) = FilelistCache::new(
+        config.paths(),
+        ImageLists::new(&xdg_dirs),
+        MediaIndex::new(&xdg_dirs),
+        &mut hotwatch,
+        event_loop.handle(),
+    )
+    .wrap_err("Failed to create FilelistCache")?;
     let filelist_cache = Rc::new(RefCell::new(filelist_cache));
 
     let (image_loader_ping, ping_source) = calloop::ping::make_ping()
@@ -168,7 +186,16 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
         })
         .map_err(|e| eyre!("{e}"))
         .wrap_err("Failed to insert the image loader listener into the event loop")?;
-    let image_loader = Rc::new(RefCell::new(ImageLoader::new(image_loader_ping)));
+    let image_loader = Rc::new(RefCell::new(ImageLoader::new(
+        event_loop.handle(),
+        image_loader_ping,
+        opts.cache_size * 1024 * 1024,
+        DecodeLimits {
+            max_pixels: opts.max_megapixels * 1_000_000,
+            max_memory: opts.max_decode_memory * 1024 * 1024,
+            timeout: Duration::from_secs(opts.decode_timeout),
+        },
+    )));
 
     let mut wpaperd = Wpaperd::new(
         &qh,
@@ -178,6 +205,7 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
         filelist_cache.clone(),
         image_loader,
         xdg_dirs,
+        event_loop.handle(),
     )
     .wrap_err("Failed to initiliaze wpaperd status")?;
 
@@ -186,10 +214,11 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
         .wrap_err("Failed to listen to IPC socket")?;
 
     // Add source to calloop loop.
+    let qh_clone = qh.clone();
     event_loop
         .handle()
-        .insert_source(socket, |stream, _, wpaperd| {
-            if let Err(err) = handle_message(stream, qh.clone(), wpaperd) {
+        .insert_source(socket, move |stream, _, wpaperd| {
+            if let Err(err) = handle_message(stream, qh_clone.clone(), wpaperd) {
                 error!("{err:?}");
             }
         })?;
@@ -223,6 +252,9 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
 
     loop {
         if should_exit.load(Ordering::Acquire) {
+            // Save the time left on each timer, so that we can resume from there
+            wpaperd.save_state();
+            filelist_cache.borrow().save_index();
             break Ok(());
         }
 
@@ -267,12 +299,14 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
                 surface.resize(&qh).ok();
                 // Add the first timer, it will run endlessly or it will be updated in
                 // Surface::handle_new_duration
-                surface.add_timer(&event_loop.handle(), None);
+                let duration_left = surface.take_restored_duration();
+                surface.add_timer(&event_loop.handle(), duration_left);
                 surface.try_drawing(&qh);
             } else {
                 // If the surface has already been drawn for the first time, then handle pausing/resuming
                 // the automatic wallpaper sequence.
                 surface.handle_pause_state(&event_loop.handle());
+                surface.play_animation(&qh);
             };
         });
 
@@ -281,12 +315,20 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
         event_loop
             .dispatch(None, &mut wpaperd)
             .wrap_err("Failed to dispatch the event loop")?;
+
+        // The displays might have been moved or the config changed
+        wpaperd.update_span_areas(&qh);
+        wpaperd.save_state_if_changed();
     }
 }
 
 fn main() -> Result<()> {
     color_eyre::install().wrap_err("Failed to inject color_eyre")?;
 
+    // Decode JPEG XL images through image
+    #[cfg(feature = "jxl")]
+    jxl_oxide::integration::register_image_decoding_hook();
+
     let xdg_dirs = BaseDirectories::with_prefix("wpaperd");
 
     let opts = Opts::parse();
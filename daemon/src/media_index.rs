@@ -0,0 +1,401 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, Metadata},
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::SystemTime,
+};
+
+use color_eyre::{eyre::WrapErr, Result};
+use image::{metadata::Orientation, ImageDecoder, ImageReader};
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use xdg::BaseDirectories;
+
+use crate::{filelist_cache::Dimensions, state::write_atomically};
+
+/// Identify a version of a file. The metadata of an image is valid as long as it doesn't change
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileKey {
+    pub mtime: Option<SystemTime>,
+    pub size: u64,
+}
+
+impl FileKey {
+    pub fn new(path: &Path) -> Option<Self> {
+        path.metadata().ok().map(|metadata| Self::from(&metadata))
+    }
+}
+
+impl From<&Metadata> for FileKey {
+    fn from(metadata: &Metadata) -> Self {
+        Self {
+            mtime: metadata.modified().ok(),
+            size: metadata.len(),
+        }
+    }
+}
+
+/// Result of the last attempt at decoding an image
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DecodeStatus {
+    /// The image has not been decoded yet
+    #[default]
+    Unknown,
+    Ok,
+    Failed,
+}
+
+/// Metadata of an image
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MediaEntry {
+    #[serde(flatten)]
+    pub key: FileKey,
+    /// Width and height after applying the orientation. None if the header couldn't be read
+    pub dimensions: Option<Dimensions>,
+    /// EXIF orientation of the image
+    pub orientation: Option<u8>,
+    /// When the photo was taken, used to sort the images
+    pub exif_date: Option<ExifDate>,
+    /// Only known once the image has been decoded
+    pub average_color: Option<[u8; 3]>,
+    pub status: DecodeStatus,
+}
+
+impl MediaEntry {
+    /// An entry whose header has not been read
+    fn new(key: FileKey) -> Self {
+        Self {
+            key,
+            dimensions: None,
+            orientation: None,
+            exif_date: None,
+            average_color: None,
+            status: DecodeStatus::Unknown,
+        }
+    }
+
+    /// Read the metadata from the header of the image, without decoding it
+    pub fn probe(path: &Path, key: FileKey) -> Self {
+        #[cfg(feature = "svg")]
+        if crate::image_format::is_svg(path) {
+            return Self {
+                dimensions: crate::svg::dimensions(path),
+                ..Self::new(key)
+            };
+        }
+        let header = (|| {
+            let mut decoder = ImageReader::open(path)
+                .ok()?
+                .with_guessed_format()
+                .ok()?
+                .into_decoder()
+                .ok()?;
+            let dimensions = decoder.dimensions();
+            Some((
+                dimensions,
+                crate::image_format::orientation(path, &mut decoder).ok(),
+            ))
+        })();
+        let (dimensions, orientation) = match header {
+            Some(((width, height), orientation)) => {
+                let orientation = orientation.unwrap_or(Orientation::NoTransforms);
+                let dimensions = match orientation {
+                    Orientation::Rotate90
+                    | Orientation::Rotate270
+                    | Orientation::Rotate90FlipH
+                    | Orientation::Rotate270FlipH => (height, width),
+                    Orientation::NoTransforms
+                    | Orientation::Rotate180
+                    | Orientation::FlipHorizontal
+                    | Orientation::FlipVertical => (width, height),
+                };
+                (Some(dimensions), Some(orientation.to_exif()))
+            }
+            None => (None, None),
+        };
+        Self {
+            dimensions,
+            orientation,
+            exif_date: exif_date(path),
+            ..Self::new(key)
+        }
+    }
+}
+
+/// Date and time as (year, month, day, hour, minute, second), so that it can be compared
+pub type ExifDate = (u16, u8, u8, u8, u8, u8);
+
+/// Read the EXIF DateTimeOriginal tag of an image
+fn exif_date(path: &Path) -> Option<ExifDate> {
+    let file = File::open(path).ok()?;
+    let exif = exif::Reader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .ok()?;
+    let field = exif.get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY)?;
+    match &field.value {
+        exif::Value::Ascii(values) => values
+            .first()
+            .and_then(|value| exif::DateTime::from_ascii(value).ok())
+            .map(|date| {
+                (
+                    date.year,
+                    date.month,
+                    date.day,
+                    date.hour,
+                    date.minute,
+                    date.second,
+                )
+            }),
+        _ => None,
+    }
+}
+
+/// The content of the index file
+#[derive(Serialize, Deserialize)]
+struct IndexFile<E> {
+    version: u32,
+    entries: E,
+}
+
+/// Metadata of all the images found, stored in $XDG_CACHE_HOME/wpaperd/index.json,
+/// so that they don't have to be read again at each start
+#[derive(Debug, Default)]
+pub struct MediaIndex {
+    /// Shared with the threads reading the directories and the headers
+    entries: Arc<HashMap<PathBuf, MediaEntry>>,
+    /// Where the index is saved. The index is not saved when it's None
+    path: Option<PathBuf>,
+    /// Incremented by every save, so that an older snapshot never replaces a newer one
+    save_generation: AtomicU64,
+    /// Held while writing the index, it contains the generation of the last snapshot written
+    save_lock: Arc<Mutex<u64>>,
+}
+
+impl MediaIndex {
+    const FILENAME: &'static str = "index.json";
+    /// Increment when the format of the entries changes, the old index will be discarded
+    const VERSION: u32 = 2;
+
+    /// Load the index from $XDG_CACHE_HOME/wpaperd
+    pub fn new(xdg_dirs: &BaseDirectories) -> Self {
+        match xdg_dirs
+            .place_cache_file(Self::FILENAME)
+            .wrap_err("Failed to create the cache directory $XDG_CACHE_HOME/wpaperd")
+        {
+            Ok(path) => Self::load(path),
+            Err(err) => {
+                warn!(
+                    "{:?}",
+                    err.wrap_err("The metadata of the images will not be saved")
+                );
+                Self::default()
+            }
+        }
+    }
+
+    /// Load the index from `path`. A missing, invalid or outdated file results in an empty index
+    fn load(path: PathBuf) -> Self {
+        let res = if path.exists() {
+            fs::read(&path)
+                .wrap_err_with(|| format!("Failed to read the index file {path:?}"))
+                .and_then(|content| {
+                    serde_json::from_slice::<IndexFile<HashMap<PathBuf, MediaEntry>>>(&content)
+                        .wrap_err_with(|| format!("Failed to parse the index file {path:?}"))
+                })
+                .map(|index| {
+                    if index.version == Self::VERSION {
+                        index.entries
+                    } else {
+                        HashMap::new()
+                    }
+                })
+        } else {
+            Ok(HashMap::new())
+        };
+        let entries = res.unwrap_or_else(|err| {
+            warn!(
+                "{:?}",
+                err.wrap_err("The metadata of the images will be read again")
+            );
+            HashMap::new()
+        });
+        Self {
+            entries: Arc::new(entries),
+            path: Some(path),
+            save_generation: AtomicU64::new(0),
+            save_lock: Arc::new(Mutex::new(0)),
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, path: &Path) -> Option<&MediaEntry> {
+        self.entries.get(path)
+    }
+
+    /// Return all the entries, to be used from another thread
+    #[inline]
+    pub fn entries(&self) -> Arc<HashMap<PathBuf, MediaEntry>> {
+        self.entries.clone()
+    }
+
+    pub fn extend(&mut self, entries: impl IntoIterator<Item = (PathBuf, MediaEntry)>) {
+        Arc::make_mut(&mut self.entries).extend(entries);
+    }
+
+    /// Record that `path` has been decoded. `probed` has been read by the thread decoding the
+    /// image, it replaces the current entry if the file changed
+    pub fn set_decoded(&mut self, path: &Path, average_color: [u8; 3], probed: MediaEntry) {
+        let entry = Arc::make_mut(&mut self.entries)
+            .entry(path.to_path_buf())
+            .and_modify(|entry| {
+                if entry.key != probed.key {
+                    *entry = probed.clone();
+                }
+            })
+            .or_insert(probed);
+        entry.status = DecodeStatus::Ok;
+        entry.average_color = Some(average_color);
+    }
+
+    /// Record that `path` could not be decoded. Its header is not read, the image will be
+    /// skipped anyway
+    pub fn set_failed(&mut self, path: &Path) {
+        let Some(key) = FileKey::new(path) else {
+            return;
+        };
+        let entry = Arc::make_mut(&mut self.entries)
+            .entry(path.to_path_buf())
+            .or_insert_with(|| MediaEntry::new(key));
+        // The file changed since it has been probed
+        if entry.key != key {
+            *entry = MediaEntry::new(key);
+        }
+        entry.status = DecodeStatus::Failed;
+        entry.average_color = None;
+    }
+
+    /// Remove the entries for which `keep` returns false. Return true if any has been removed
+    pub fn retain(&mut self, mut keep: impl FnMut(&Path) -> bool) -> bool {
+        if self.entries.keys().all(|path| keep(path)) {
+            return false;
+        }
+        Arc::make_mut(&mut self.entries).retain(|path, _| keep(path));
+        true
+    }
+
+    /// Remove the entries of the images in `root` that are not in `files` anymore.
+    /// Only the images directly inside `root` are considered, unless `recursive` is true
+    pub fn prune(&mut self, root: &Path, recursive: bool, files: &[PathBuf]) {
+        let is_stale = |path: &PathBuf| {
+            ((recursive && path.starts_with(root)) || path.parent() == Some(root))
+                && files.binary_search(path).is_err()
+        };
+        if self.entries.keys().any(is_stale) {
+            Arc::make_mut(&mut self.entries).retain(|path, _| !is_stale(path));
+        }
+    }
+
+    /// Write the index to disk
+    pub fn save(&self) {
+        if let Some(path) = &self.path {
+            let generation = self.save_generation.fetch_add(1, Ordering::Relaxed) + 1;
+            if let Err(err) = write_index(path, &self.entries, generation, &self.save_lock) {
+                error!("{err:?}");
+            }
+        }
+    }
+
+    /// Write the index to disk from another thread
+    pub fn save_in_background(&self) {
+        if let Some(path) = self.path.clone() {
+            let entries = self.entries.clone();
+            let generation = self.save_generation.fetch_add(1, Ordering::Relaxed) + 1;
+            let save_lock = self.save_lock.clone();
+            rayon::spawn(move || {
+                if let Err(err) = write_index(&path, &entries, generation, &save_lock) {
+                    error!("{err:?}");
+                }
+            });
+        }
+    }
+}
+
+/// Write the snapshot `entries` taken at `generation`, unless a newer one has already been
+/// written by another save
+fn write_index(
+    path: &Path,
+    entries: &HashMap<PathBuf, MediaEntry>,
+    generation: u64,
+    save_lock: &Mutex<u64>,
+) -> Result<()> {
+    let mut written = save_lock.lock().unwrap();
+    if *written > generation {
+        return Ok(());
+    }
+    let content = serde_json::to_vec(&IndexFile {
+        version: MediaIndex::VERSION,
+        entries,
+    })
+    .wrap_err("Failed to serialize the index of the images")?;
+    write_atomically(path, &content)?;
+    *written = generation;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prune() {
+        let key = FileKey {
+            mtime: None,
+            size: 0,
+        };
+        let mut index = MediaIndex::default();
+        index.extend(
+            ["/a/1.png", "/a/2.png", "/a/b/3.png", "/c/4.png"]
+                .into_iter()
+                .map(|path| (PathBuf::from(path), MediaEntry::probe(Path::new(path), key))),
+        );
+
+        // The images in the subdirectories belong to another filelist
+        index.prune(Path::new("/a"), false, &[PathBuf::from("/a/1.png")]);
+        let mut paths: Vec<_> = index.entries.keys().cloned().collect();
+        paths.sort_unstable();
+        assert_eq!(
+            paths,
+            ["/a/1.png", "/a/b/3.png", "/c/4.png"].map(PathBuf::from)
+        );
+
+        index.prune(Path::new("/a"), true, &[]);
+        assert_eq!(
+            index.entries.keys().cloned().collect::<Vec<_>>(),
+            vec![PathBuf::from("/c/4.png")]
+        );
+    }
+
+    #[test]
+    fn test_write_index_skips_older_snapshots() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(MediaIndex::FILENAME);
+        let key = FileKey {
+            mtime: None,
+            size: 0,
+        };
+        let newer = HashMap::from([(PathBuf::from("/a/1.png"), MediaEntry::new(key))]);
+        let save_lock = Mutex::new(0);
+
+        write_index(&path, &newer, 2, &save_lock).unwrap();
+        // Serialized earlier but written last, it must not replace the newer snapshot
+        write_index(&path, &HashMap::new(), 1, &save_lock).unwrap();
+        let index = MediaIndex::load(path);
+        assert!(index.get(Path::new("/a/1.png")).is_some());
+    }
+}
@@ -30,4 +30,31 @@ pub struct Opts {
         help = "Readiness fd used by wpaperd to signal that it has started correctly"
     )]
     pub notify: Option<u8>,
+    #[clap(
+        long,
+        default_value_t = 256,
+        help = "Memory used to keep the decoded images, in MiB (0 to disable the cache)"
+    )]
+    pub cache_size: usize,
+    #[clap(
+        long,
+        default_value_t = 100,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Maximum size of the images, in megapixels; bigger images are rejected"
+    )]
+    pub max_megapixels: u64,
+    #[clap(
+        long,
+        default_value_t = 512,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Maximum memory used to decode an image, in MiB; the images needing more are rejected"
+    )]
+    pub max_decode_memory: u64,
+    #[clap(
+        long,
+        default_value_t = 30,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Maximum time spent decoding an image, in seconds; slower images are rejected"
+    )]
+    pub decode_timeout: u64,
 }
@@ -0,0 +1,412 @@
+use std::{ffi::CStr, rc::Rc};
+
+use color_eyre::{
+    eyre::{ensure, WrapErr},
+    Result,
+};
+use log::warn;
+use serde::Deserialize;
+
+use crate::{gl_check, wallpaper_info::Color};
+
+use super::{gl, shader::create_fullscreen_program};
+
+/// Texture unit used by the effects, TEXTURE0 and TEXTURE1 are used by the wallpapers
+const EFFECTS_TEXTURE_UNIT: gl::types::GLenum = gl::TEXTURE2;
+
+/// An effect applied to the wallpaper after it has been drawn
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Effect {
+    /// Gaussian blur, with the radius in pixels
+    Blur(f32),
+    /// Multiply the brightness of the colors
+    Brightness(f32),
+    /// Darken the colors, from 0.0 to 1.0
+    Dim(f32),
+    /// Multiply the saturation of the colors, 0.0 removes them
+    Saturation(f32),
+    Grayscale,
+    /// Darken the corners, from 0.0 to 1.0
+    Vignette(f32),
+    /// Mix the colors with `color`, `amount` goes from 0.0 to 1.0
+    Tint {
+        color: Color,
+        amount: f32,
+    },
+}
+
+impl Effect {
+    pub const MAX_BLUR_RADIUS: f32 = 64.0;
+
+    /// Return why the settings of the effect are invalid, if they are
+    pub fn check(&self) -> Result<(), String> {
+        let in_range = |value: f32, min: f32, max: f32| {
+            if (min..=max).contains(&value) {
+                Ok(())
+            } else {
+                Err(format!("{value} must be between {min} and {max}"))
+            }
+        };
+        match *self {
+            Effect::Blur(radius) => in_range(radius, 0.0, Self::MAX_BLUR_RADIUS),
+            Effect::Brightness(value) | Effect::Saturation(value) => in_range(value, 0.0, f32::MAX),
+            Effect::Dim(amount) | Effect::Vignette(amount) | Effect::Tint { amount, .. } => {
+                in_range(amount, 0.0, 1.0)
+            }
+            Effect::Grayscale => Ok(()),
+        }
+    }
+
+    /// The passes needed to draw the effect
+    fn passes(self) -> Vec<Pass> {
+        let adjust = Adjust::default();
+        match self {
+            Effect::Blur(radius) => vec![
+                Pass::Blur {
+                    direction: [1.0, 0.0],
+                    radius,
+                },
+                Pass::Blur {
+                    direction: [0.0, 1.0],
+                    radius,
+                },
+            ],
+            Effect::Brightness(brightness) => vec![Pass::Adjust(Adjust {
+                brightness,
+                ..adjust
+            })],
+            Effect::Dim(amount) => vec![Pass::Adjust(Adjust {
+                brightness: 1.0 - amount,
+                ..adjust
+            })],
+            Effect::Saturation(saturation) => vec![Pass::Adjust(Adjust {
+                saturation,
+                ..adjust
+            })],
+            Effect::Grayscale => vec![Pass::Adjust(Adjust {
+                saturation: 0.0,
+                ..adjust
+            })],
+            Effect::Vignette(vignette) => vec![Pass::Adjust(Adjust { vignette, ..adjust })],
+            Effect::Tint { color, amount } => {
+                let [r, g, b] = color.0.map(|channel| channel as f32 / 255.0);
+                vec![Pass::Adjust(Adjust {
+                    tint: [r, g, b, amount],
+                    ..adjust
+                })]
+            }
+        }
+    }
+}
+
+/// The values of the uniforms of [ADJUST_SHADER_SOURCE]
+#[derive(Clone, Copy, Debug)]
+struct Adjust {
+    brightness: f32,
+    saturation: f32,
+    /// The alpha is the amount of tint
+    tint: [f32; 4],
+    vignette: f32,
+}
+
+impl Default for Adjust {
+    fn default() -> Self {
+        Self {
+            brightness: 1.0,
+            saturation: 1.0,
+            tint: [0.0; 4],
+            vignette: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Pass {
+    /// Half of a Gaussian blur, along `direction`
+    Blur {
+        direction: [f32; 2],
+        radius: f32,
+    },
+    Adjust(Adjust),
+}
+
+/// A framebuffer drawing into a texture
+struct Target {
+    framebuffer: gl::types::GLuint,
+    texture: gl::types::GLuint,
+}
+
+impl Target {
+    unsafe fn new(gl: &gl::Gl, width: i32, height: i32) -> Result<Self> {
+        let mut texture = 0;
+        gl.GenTextures(1, &mut texture);
+        gl_check!(gl, "Failed to generate the texture of the framebuffer");
+        allocate_texture(gl, texture, width, height)?;
+        for (parameter, value) in [
+            (gl::TEXTURE_MIN_FILTER, gl::LINEAR),
+            (gl::TEXTURE_MAG_FILTER, gl::LINEAR),
+            (gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE),
+            (gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE),
+        ] {
+            gl.TexParameteri(gl::TEXTURE_2D, parameter, value as i32);
+            gl_check!(
+                gl,
+                "Failed to set the parameters of the framebuffer texture"
+            );
+        }
+
+        let mut framebuffer = 0;
+        gl.GenFramebuffers(1, &mut framebuffer);
+        gl_check!(gl, "Failed to generate the framebuffer");
+        gl.BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+        gl_check!(gl, "Failed to bind the framebuffer");
+        gl.FramebufferTexture2D(
+            gl::FRAMEBUFFER,
+            gl::COLOR_ATTACHMENT0,
+            gl::TEXTURE_2D,
+            texture,
+            0,
+        );
+        gl_check!(gl, "Failed to attach the texture to the framebuffer");
+        let status = gl.CheckFramebufferStatus(gl::FRAMEBUFFER);
+        gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gl_check!(gl, "Failed to unbind the framebuffer");
+        ensure!(
+            status == gl::FRAMEBUFFER_COMPLETE,
+            "The framebuffer is incomplete: {status}"
+        );
+
+        Ok(Self {
+            framebuffer,
+            texture,
+        })
+    }
+
+    unsafe fn resize(&self, gl: &gl::Gl, width: i32, height: i32) -> Result<()> {
+        allocate_texture(gl, self.texture, width, height)
+    }
+
+    unsafe fn delete(&self, gl: &gl::Gl) {
+        gl.DeleteFramebuffers(1, &self.framebuffer);
+        gl.DeleteTextures(1, &self.texture);
+    }
+}
+
+/// Allocate `texture` for a display of `width`x`height`
+unsafe fn allocate_texture(
+    gl: &gl::Gl,
+    texture: gl::types::GLuint,
+    width: i32,
+    height: i32,
+) -> Result<()> {
+    gl.ActiveTexture(EFFECTS_TEXTURE_UNIT);
+    gl_check!(gl, "Failed to activate the texture TEXTURE2");
+    gl.BindTexture(gl::TEXTURE_2D, texture);
+    gl_check!(gl, "Failed to bind the framebuffer texture");
+    gl.TexImage2D(
+        gl::TEXTURE_2D,
+        0,
+        gl::RGBA as i32,
+        width.max(1),
+        height.max(1),
+        0,
+        gl::RGBA,
+        gl::UNSIGNED_BYTE,
+        std::ptr::null(),
+    );
+    gl_check!(gl, "Failed to allocate the framebuffer texture");
+    Ok(())
+}
+
+/// Draw the wallpaper into a texture and apply the effects on it, one pass after the other.
+/// The passes draw alternatively into the two targets, the last one draws on the display
+pub struct Effects {
+    gl: Rc<gl::Gl>,
+    passes: Vec<Pass>,
+    blur_program: gl::types::GLuint,
+    adjust_program: gl::types::GLuint,
+    targets: [Target; 2],
+    width: i32,
+    height: i32,
+}
+
+impl Effects {
+    /// Return None when there are no effects to apply. The program in use is changed
+    pub unsafe fn new(
+        gl: Rc<gl::Gl>,
+        effects: &[Effect],
+        width: i32,
+        height: i32,
+    ) -> Result<Option<Self>> {
+        let passes: Vec<Pass> = effects.iter().flat_map(|effect| effect.passes()).collect();
+        if passes.is_empty() {
+            return Ok(None);
+        }
+        let blur_program = create_effect_program(&gl, BLUR_SHADER_SOURCE)
+            .wrap_err("Failed to create the blur program")?;
+        let adjust_program = create_effect_program(&gl, ADJUST_SHADER_SOURCE)
+            .wrap_err("Failed to create the program for the color effects")?;
+        let targets = [
+            Target::new(&gl, width, height)?,
+            Target::new(&gl, width, height)?,
+        ];
+        Ok(Some(Self {
+            gl,
+            passes,
+            blur_program,
+            adjust_program,
+            targets,
+            width,
+            height,
+        }))
+    }
+
+    pub fn resize(&mut self, width: i32, height: i32) -> Result<()> {
+        self.width = width;
+        self.height = height;
+        for target in &self.targets {
+            unsafe { target.resize(&self.gl, width, height)? };
+        }
+        Ok(())
+    }
+
+    /// Draw the wallpaper into the first target, instead of the display
+    pub unsafe fn bind(&self) -> Result<()> {
+        self.gl
+            .BindFramebuffer(gl::FRAMEBUFFER, self.targets[0].framebuffer);
+        gl_check!(self.gl, "Failed to bind the framebuffer of the effects");
+        Ok(())
+    }
+
+    /// Apply all the effects and draw the result on the display. The program in use is changed
+    pub unsafe fn apply(&self) -> Result<()> {
+        let gl = &self.gl;
+        gl.ActiveTexture(EFFECTS_TEXTURE_UNIT);
+        gl_check!(gl, "Failed to activate the texture TEXTURE2");
+        for (i, pass) in self.passes.iter().enumerate() {
+            let output = if i + 1 == self.passes.len() {
+                0
+            } else {
+                self.targets[(i + 1) % 2].framebuffer
+            };
+            gl.BindFramebuffer(gl::FRAMEBUFFER, output);
+            gl_check!(gl, "Failed to bind the framebuffer of the effects");
+            gl.BindTexture(gl::TEXTURE_2D, self.targets[i % 2].texture);
+            gl_check!(gl, "Failed to bind the texture of the effects");
+
+            let program = match pass {
+                Pass::Blur { .. } => self.blur_program,
+                Pass::Adjust(_) => self.adjust_program,
+            };
+            gl.UseProgram(program);
+            gl_check!(gl, "Failed to switch to the program of the effect");
+            let uniform = |name: &CStr| gl.GetUniformLocation(program, name.as_ptr());
+            match *pass {
+                Pass::Blur { direction, radius } => {
+                    let step = [
+                        direction[0] / self.width.max(1) as f32,
+                        direction[1] / self.height.max(1) as f32,
+                    ];
+                    gl.Uniform2fv(uniform(c"texelStep"), 1, step.as_ptr());
+                    gl.Uniform1f(uniform(c"radius"), radius);
+                }
+                Pass::Adjust(adjust) => {
+                    gl.Uniform1f(uniform(c"brightness"), adjust.brightness);
+                    gl.Uniform1f(uniform(c"saturation"), adjust.saturation);
+                    gl.Uniform4fv(uniform(c"tint"), 1, adjust.tint.as_ptr());
+                    gl.Uniform1f(uniform(c"vignette"), adjust.vignette);
+                }
+            }
+            gl_check!(gl, "Failed to set the uniforms of the effect");
+
+            gl.DrawElements(gl::TRIANGLES, 6, gl::UNSIGNED_INT, std::ptr::null());
+            gl_check!(gl, "Failed to draw the effect");
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Effects {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.DeleteProgram(self.blur_program);
+            self.gl.DeleteProgram(self.adjust_program);
+            for target in &self.targets {
+                target.delete(&self.gl);
+            }
+        }
+        let check_err = || -> Result<()> {
+            unsafe {
+                gl_check!(self.gl, "Failed to delete the objects of the effects");
+            }
+            Ok(())
+        };
+        if let Err(err) = check_err() {
+            warn!("{err:?}");
+        }
+    }
+}
+
+unsafe fn create_effect_program(gl: &gl::Gl, fragment: &CStr) -> Result<gl::types::GLuint> {
+    let program = create_fullscreen_program(gl, &[fragment.as_ptr()])?;
+    gl.UseProgram(program);
+    gl_check!(gl, "Failed to switch to the newly created openGL program");
+    let loc = gl.GetUniformLocation(program, c"u_texture".as_ptr());
+    gl_check!(gl, "Failed to get the uniform location for u_texture");
+    ensure!(loc >= 0, "Failed to find the uniform u_texture");
+    gl.Uniform1i(loc, (EFFECTS_TEXTURE_UNIT - gl::TEXTURE0) as i32);
+    gl_check!(gl, "Failed to set the value for uniform u_texture");
+
+    Ok(program)
+}
+
+const BLUR_SHADER_SOURCE: &CStr = c"
+#version 300 es
+precision highp float;
+out vec4 FragColor;
+
+in vec2 v_texcoord;
+
+uniform sampler2D u_texture;
+// Distance between two pixels along the direction of the blur
+uniform vec2 texelStep;
+uniform float radius;
+
+void main() {
+    float sigma = max(radius / 2.0, 0.5);
+    int samples = int(ceil(radius));
+    vec4 color = vec4(0.0);
+    float total = 0.0;
+    for (int i = -samples; i <= samples; i++) {
+        float weight = exp(-float(i * i) / (2.0 * sigma * sigma));
+        color += texture(u_texture, v_texcoord + float(i) * texelStep) * weight;
+        total += weight;
+    }
+    FragColor = color / total;
+}";
+
+const ADJUST_SHADER_SOURCE: &CStr = c"
+#version 300 es
+precision highp float;
+out vec4 FragColor;
+
+in vec2 v_texcoord;
+
+uniform sampler2D u_texture;
+uniform float brightness;
+uniform float saturation;
+uniform vec4 tint;
+uniform float vignette;
+
+void main() {
+    vec4 color = texture(u_texture, v_texcoord);
+    vec3 rgb = color.rgb * brightness;
+    float luma = dot(rgb, vec3(0.2126, 0.7152, 0.0722));
+    rgb = mix(vec3(luma), rgb, saturation);
+    rgb = mix(rgb, tint.rgb, tint.a);
+    // 0.0 in the center and 1.0 in the corners
+    float distance = length(v_texcoord - 0.5) * 1.41421356;
+    rgb *= 1.0 - vignette * smoothstep(0.3, 1.0, distance);
+    FragColor = vec4(clamp(rgb, 0.0, 1.0), color.a);
+}";
@@ -1,3 +1,5 @@
+use std::path::Path;
+
 use image::DynamicImage;
 use log::warn;
 use smithay_client_toolkit::reexports::client::{protocol::wl_surface::WlSurface, Proxy};
@@ -12,10 +14,10 @@ use color_eyre::{
 
 use crate::{
     display_info::DisplayInfo,
-    wallpaper_info::{BackgroundMode, WallpaperInfo},
+    wallpaper_info::{Background, BackgroundMode, WallpaperInfo},
 };
 
-use super::Renderer;
+use super::{Renderer, Transition};
 
 pub struct EglContext {
     display: egl::Display,
@@ -100,6 +102,7 @@ impl EglContext {
             Renderer::new(
                 wallpaper_info.transition_time,
                 wallpaper_info.transition.clone(),
+                &wallpaper_info.effects,
                 display_info,
             )
             .wrap_err("Failed to create a openGL ES renderer")
@@ -182,7 +185,7 @@ impl EglContext {
 
     pub fn load_wallpaper(
         &mut self,
-        image: DynamicImage,
+        image: &DynamicImage,
         background_mode: BackgroundMode,
         offset: Option<f32>,
         display_info: &DisplayInfo,
@@ -195,6 +198,48 @@ impl EglContext {
             .load_wallpaper(image, background_mode, offset, display_info)
     }
 
+    pub fn load_background(
+        &mut self,
+        background: &Background,
+        background_mode: BackgroundMode,
+        offset: Option<f32>,
+        display_info: &DisplayInfo,
+    ) -> Result<()> {
+        self.make_current()
+            .wrap_err("Failed to switch EGL context")?;
+        self.renderer
+            .load_background(background, background_mode, offset, display_info)
+    }
+
+    pub fn load_shader(
+        &mut self,
+        path: &Path,
+        background_mode: BackgroundMode,
+        offset: Option<f32>,
+        display_info: &DisplayInfo,
+    ) -> Result<()> {
+        self.make_current()
+            .wrap_err("Failed to switch EGL context")?;
+        self.renderer
+            .load_shader(path, background_mode, offset, display_info)
+    }
+
+    /// Use `transition` from now on, for the wallpaper that has just been loaded
+    pub fn switch_transition(
+        &mut self,
+        transition: &Transition,
+        background_mode: BackgroundMode,
+        offset: Option<f32>,
+        display_info: &DisplayInfo,
+    ) -> Result<()> {
+        self.make_current()
+            .wrap_err("Failed to switch EGL context")?;
+        self.renderer
+            .switch_transition(transition, display_info.transform)?;
+        self.renderer
+            .set_mode(background_mode, offset, display_info)
+    }
+
     pub fn draw(&mut self) -> Result<()> {
         unsafe { self.renderer.draw()? }
 
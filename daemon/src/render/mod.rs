@@ -1,8 +1,10 @@
 mod coordinates;
+mod effects;
 mod egl_context;
 mod renderer;
 mod shader;
-mod transition;
+mod shader_wallpaper;
+pub(crate) mod transition;
 mod wallpaper;
 
 use std::ffi::{c_void, CStr};
@@ -11,8 +13,10 @@ use color_eyre::Result;
 use coordinates::{get_opengl_point_coordinates, Coordinates};
 use image::DynamicImage;
 
+pub use effects::Effect;
 pub use egl_context::EglContext;
 pub use renderer::Renderer;
+pub use shader_wallpaper::{is_shader, ShaderClock, DEFAULT_MAX_FPS};
 pub use transition::Transition;
 
 pub mod gl {
@@ -126,7 +130,7 @@ fn initialize_objects(gl: &gl::Gl) -> Result<(gl::types::GLuint, gl::types::GLui
     }
 }
 
-fn load_texture(gl: &gl::Gl, image: DynamicImage) -> Result<()> {
+fn load_texture(gl: &gl::Gl, image: &DynamicImage) -> Result<()> {
     unsafe {
         gl.TexImage2D(
             gl::TEXTURE_2D,
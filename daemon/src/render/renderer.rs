@@ -1,30 +1,59 @@
-use std::{ffi::CStr, ops::Deref, rc::Rc, time::Instant};
+use std::{ffi::CStr, ops::Deref, path::Path, rc::Rc, time::Instant};
 
 use color_eyre::{
     eyre::{ensure, OptionExt, WrapErr},
-    Result,
+    owo_colors::OwoColorize,
+    Result, Section,
 };
 use egl::API as egl;
-use image::{DynamicImage, RgbaImage};
+use image::{DynamicImage, Rgba, RgbaImage};
 use log::error;
 use smithay_client_toolkit::reexports::client::protocol::wl_output::Transform;
 
 use crate::{
-    display_info::DisplayInfo,
+    display_info::{DisplayInfo, SpanArea},
     gl_check,
     render::{
         initialize_objects,
         shader::{create_shader, FRAGMENT_SHADER_SOURCE, VERTEX_SHADER_SOURCE},
     },
-    wallpaper_info::BackgroundMode,
+    wallpaper_info::{Background, BackgroundMode},
 };
 
-use super::{gl, wallpaper::Wallpaper, Transition};
+use super::{
+    effects::Effects, gl, shader_wallpaper::ShaderWallpaper, wallpaper::Wallpaper, Effect,
+    Transition,
+};
 
 fn black_image() -> RgbaImage {
     RgbaImage::from_raw(1, 1, vec![0, 0, 0, 255]).unwrap()
 }
 
+/// Generate the image of `background` for a display of `width`x`height`. A solid color only
+/// needs a single pixel, since it gets stretched over the display
+fn background_image(background: &Background, width: u32, height: u32) -> RgbaImage {
+    match background {
+        Background::Color(color) => RgbaImage::from_pixel(1, 1, Rgba(color.rgba())),
+        Background::Gradient(gradient) => {
+            let (width, height) = (width.max(1), height.max(1));
+            // Same as CSS linear-gradient: 0 degrees goes up, 90 goes right, and the gradient
+            // line is long enough for the corners to have the start and end colors
+            let (sin, cos) = gradient.angle.to_radians().sin_cos();
+            let length = (width as f32 * sin).abs() + (height as f32 * cos).abs();
+            let from = gradient.from.rgba().map(f32::from);
+            let to = gradient.to.rgba().map(f32::from);
+            RgbaImage::from_fn(width, height, |x, y| {
+                let x = x as f32 + 0.5 - width as f32 / 2.0;
+                let y = y as f32 + 0.5 - height as f32 / 2.0;
+                let t = ((x * sin - y * cos) / length + 0.5).clamp(0.0, 1.0);
+                Rgba(std::array::from_fn(|i| {
+                    (from[i] + (to[i] - from[i]) * t).round() as u8
+                }))
+            })
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum TransitionStatus {
     /// Transition has been requested but no frame has been drawn yet.
@@ -40,6 +69,9 @@ pub enum TransitionStatus {
 pub struct Renderer {
     gl: Rc<gl::Gl>,
     pub program: gl::types::GLuint,
+    /// The programs created for the transitions used so far, including the current one, so that
+    /// switching between them doesn't need to compile them again
+    programs: TransitionPrograms,
     vbo: gl::types::GLuint,
     eab: gl::types::GLuint,
     // milliseconds time for the transition
@@ -49,12 +81,17 @@ pub struct Renderer {
     //transparent_texture: gl::types::GLuint,
     /// contains the progress of the current animation
     transition_status: TransitionStatus,
+    /// Applied after the wallpaper has been drawn, None if there are no effects
+    effects: Option<Effects>,
+    /// Renders the current wallpaper, when it is a shader
+    shader: Option<ShaderWallpaper>,
 }
 
 impl Renderer {
     pub unsafe fn new(
         transition_time: u32,
         transition: Transition,
+        effects: &[Effect],
         display_info: &DisplayInfo,
     ) -> Result<Self> {
         let gl = Rc::new(gl::Gl::load_with(|name| {
@@ -63,23 +100,36 @@ impl Renderer {
                 .unwrap() as *const std::ffi::c_void
         }));
 
-        let program =
-            create_program(&gl, transition).wrap_err("Failed to create openGL program")?;
+        let program = create_transition_program(&gl, transition.clone())
+            .wrap_err("Failed to create openGL program")?;
 
         let (vbo, eab) = initialize_objects(&gl).wrap_err("Failed to initialize openGL objects")?;
 
-        let current_wallpaper = Wallpaper::new(gl.clone(), black_image().into(), true)?;
-        let prev_wallpaper = Wallpaper::new(gl.clone(), black_image().into(), false)?;
+        let current_wallpaper = Wallpaper::new(gl.clone(), &black_image().into(), true)?;
+        let prev_wallpaper = Wallpaper::new(gl.clone(), &black_image().into(), false)?;
+
+        let effects = Effects::new(
+            gl.clone(),
+            effects,
+            display_info.adjusted_width(),
+            display_info.adjusted_height(),
+        )
+        .wrap_err("Failed to create the effects")?;
+        gl.UseProgram(program);
+        gl_check!(gl, "Failed to switch to the openGL program");
 
         let renderer = Self {
             gl,
             program,
+            programs: TransitionPrograms(vec![(transition, program)]),
             vbo,
             eab,
             transition_time,
             prev_wallpaper,
             current_wallpaper,
             transition_status: TransitionStatus::Ended,
+            effects,
+            shader: None,
         };
 
         renderer
@@ -98,6 +148,9 @@ impl Renderer {
     }
 
     pub unsafe fn draw(&mut self) -> Result<()> {
+        if let Some(effects) = &self.effects {
+            effects.bind()?;
+        }
         self.gl.Clear(gl::COLOR_BUFFER_BIT);
         self.check_error("Failed to clear the screen")?;
 
@@ -119,9 +172,31 @@ impl Renderer {
             .DrawElements(gl::TRIANGLES, 6, gl::UNSIGNED_INT, std::ptr::null());
         self.check_error("Failed to draw the vertices")?;
 
+        if let Some(effects) = &self.effects {
+            effects.apply().wrap_err("Failed to apply the effects")?;
+            self.gl.UseProgram(self.program);
+            self.check_error("Failed to switch back to the transition program")?;
+        }
+
         Ok(())
     }
 
+    /// Apply `effects` from the next draw
+    pub fn update_effects(&mut self, effects: &[Effect], display_info: &DisplayInfo) -> Result<()> {
+        // Delete the previous objects first
+        self.effects = None;
+        unsafe {
+            self.effects = Effects::new(
+                self.gl.clone(),
+                effects,
+                display_info.adjusted_width(),
+                display_info.adjusted_height(),
+            )?;
+            self.gl.UseProgram(self.program);
+            self.check_error("Failed to switch back to the transition program")
+        }
+    }
+
     /// Update the transition status using wall-clock time.
     ///
     /// Returns `true` if the transition is still running, `false` if it ended or was already over.
@@ -159,11 +234,70 @@ impl Renderer {
 
     pub fn load_wallpaper(
         &mut self,
-        image: DynamicImage,
+        image: &DynamicImage,
+        mode: BackgroundMode,
+        offset: Option<f32>,
+        display_info: &DisplayInfo,
+    ) -> Result<()> {
+        self.swap_wallpapers(image)?;
+        self.set_mode(mode, offset, display_info)
+    }
+
+    /// Generate `background` for the display and show it in place of the current wallpaper
+    pub fn load_background(
+        &mut self,
+        background: &Background,
+        mode: BackgroundMode,
+        offset: Option<f32>,
+        display_info: &DisplayInfo,
+    ) -> Result<()> {
+        let image = background_image(
+            background,
+            display_info.scaled_width() as u32,
+            display_info.scaled_height() as u32,
+        );
+        self.swap_wallpapers(&image.into())?;
+        self.current_wallpaper.stretch();
+        // The mode is still needed for the previous wallpaper
+        self.set_mode(mode, offset, display_info)
+    }
+
+    /// Compile the shader at `path` and render its first frame in place of the current wallpaper
+    pub fn load_shader(
+        &mut self,
+        path: &Path,
         mode: BackgroundMode,
         offset: Option<f32>,
         display_info: &DisplayInfo,
     ) -> Result<()> {
+        let mut shader = unsafe { ShaderWallpaper::new(self.gl.clone(), path)? };
+        let (width, height) = shader_size(display_info);
+        self.swap_wallpapers(&DynamicImage::new_rgba8(width as u32, height as u32))?;
+        self.current_wallpaper.stretch();
+        unsafe {
+            shader.attach(self.current_wallpaper.texture(), width, height)?;
+        }
+        self.shader = Some(shader);
+        self.render_shader_frame(0.0, 0)?;
+        self.set_mode(mode, offset, display_info)
+    }
+
+    /// Render the frame of the shader at `time` seconds, if the current wallpaper is a shader
+    pub fn render_shader_frame(&mut self, time: f32, frame: i32) -> Result<()> {
+        let Some(shader) = self.shader.as_mut() else {
+            return Ok(());
+        };
+        unsafe {
+            shader.render(time, frame)?;
+            self.gl.UseProgram(self.program);
+            self.check_error("Failed to switch back to the transition program")
+        }
+    }
+
+    /// Move the current wallpaper to TEXTURE0 and load `image` into TEXTURE1. A shader stops
+    /// rendering, so that its last frame is kept for the transition
+    fn swap_wallpapers(&mut self, image: &DynamicImage) -> Result<()> {
+        self.shader = None;
         std::mem::swap(&mut self.prev_wallpaper, &mut self.current_wallpaper);
         unsafe {
             self.gl.ActiveTexture(gl::TEXTURE0);
@@ -172,11 +306,13 @@ impl Renderer {
         self.prev_wallpaper.bind()?;
 
         // Load image into TEXTURE1
-        self.current_wallpaper.load_image(image, true)?;
-
-        self.set_mode(mode, offset, display_info)?;
+        self.current_wallpaper.load_image(image, true)
+    }
 
-        Ok(())
+    /// Replace the current image with the next frame of its animation, all the frames have the
+    /// same size
+    pub fn load_frame(&mut self, image: &DynamicImage) -> Result<()> {
+        self.current_wallpaper.load_image(image, true)
     }
 
     pub fn set_mode(
@@ -188,14 +324,33 @@ impl Renderer {
         let display_width = display_info.scaled_width() as f32;
         let display_height = display_info.scaled_height() as f32;
         let display_ratio = display_width / display_height;
-        let gen_texture_scale = |image_width: f32, image_height: f32| {
+        let offset = match (offset, mode) {
+            (
+                None,
+                BackgroundMode::Stretch
+                | BackgroundMode::Center
+                | BackgroundMode::Fit
+                | BackgroundMode::FitBorderColor
+                | BackgroundMode::Span,
+            ) => 0.5,
+            (None, BackgroundMode::Tile) => 0.0,
+            (Some(offset), _) => offset,
+        };
+        // Return the scale and the translation of the texture
+        let gen_texture_scale = |wallpaper: &Wallpaper| {
+            if wallpaper.is_stretched() {
+                return ([1.0, 1.0], [0.0, 0.0]);
+            }
+            let image_width = wallpaper.get_image_width() as f32;
+            let image_height = wallpaper.get_image_height() as f32;
             let image_ratio: f32 = image_width / image_height;
-            Box::new(match mode {
+            let center = [
+                (display_ratio / image_ratio).min(1.0),
+                (image_ratio / display_ratio).min(1.0),
+            ];
+            let scale = match mode {
                 BackgroundMode::Stretch => [1.0, 1.0],
-                BackgroundMode::Center => [
-                    (display_ratio / image_ratio).min(1.0),
-                    (image_ratio / display_ratio).min(1.0),
-                ],
+                BackgroundMode::Center => center,
                 BackgroundMode::Fit | BackgroundMode::FitBorderColor => {
                     // Portrait mode
                     // In this case we calculate the width relative to the height of the
@@ -232,18 +387,17 @@ impl Renderer {
                         }
                     }
                 }
-            })
+                BackgroundMode::Span => match display_info.span_area {
+                    Some(area) => return span_texture_scale(image_ratio, offset, area),
+                    // The display is not spanning with any other, treat it as Center
+                    None => center,
+                },
+            };
+            (scale, [0.0, 0.0])
         };
-        let texture_scale = gen_texture_scale(
-            self.current_wallpaper.get_image_width() as f32,
-            self.current_wallpaper.get_image_height() as f32,
-        );
-        let (prev_image_width, prev_image_height) = (
-            self.prev_wallpaper.get_image_width() as f32,
-            self.prev_wallpaper.get_image_height() as f32,
-        );
-
-        let prev_texture_scale = gen_texture_scale(prev_image_width, prev_image_height);
+        let (texture_scale, texture_translation) = gen_texture_scale(&self.current_wallpaper);
+        let (prev_texture_scale, prev_texture_translation) =
+            gen_texture_scale(&self.prev_wallpaper);
 
         unsafe {
             let loc = self
@@ -264,6 +418,28 @@ impl Renderer {
                 .Uniform2fv(loc, 1, prev_texture_scale.as_ptr() as *const _);
             self.check_error("Failed to set the value for prevTextureScale")?;
 
+            let loc = self
+                .gl
+                .GetUniformLocation(self.program, b"textureTranslation\0".as_ptr() as *const _);
+            self.check_error("Failed to get the uniform location for textureTranslation")?;
+            ensure!(loc >= 0, "Failed to find uniform textureTranslation");
+            self.gl
+                .Uniform2fv(loc, 1, texture_translation.as_ptr() as *const _);
+            self.check_error("Failed to set uniform textureTranslation")?;
+
+            let loc = self.gl.GetUniformLocation(
+                self.program,
+                b"prevTextureTranslation\0".as_ptr() as *const _,
+            );
+            self.check_error("Failed to get the uniform location for prevTextureTranslation")?;
+            ensure!(
+                loc >= 0,
+                "Failed to find the uniform prevTextureTranslation"
+            );
+            self.gl
+                .Uniform2fv(loc, 1, prev_texture_translation.as_ptr() as *const _);
+            self.check_error("Failed to set the value for prevTextureTranslation")?;
+
             let loc = self
                 .gl
                 .GetUniformLocation(self.program, b"ratio\0".as_ptr() as *const _);
@@ -271,18 +447,6 @@ impl Renderer {
             self.gl.Uniform1f(loc, display_ratio);
             self.check_error("Failed to set the value for the uniform ratio")?;
 
-            let offset = match (offset, mode) {
-                (
-                    None,
-                    BackgroundMode::Stretch
-                    | BackgroundMode::Center
-                    | BackgroundMode::Fit
-                    | BackgroundMode::FitBorderColor,
-                ) => 0.5,
-                (None, BackgroundMode::Tile) => 0.0,
-                (Some(offset), _) => offset,
-            };
-
             let loc = self
                 .gl
                 .GetUniformLocation(self.program, b"texture_offset\0".as_ptr() as *const _);
@@ -295,7 +459,7 @@ impl Renderer {
                     gl::CLAMP_TO_BORDER_EXT
                 }
                 BackgroundMode::Tile => gl::REPEAT,
-                BackgroundMode::FitBorderColor => gl::CLAMP_TO_EDGE,
+                BackgroundMode::FitBorderColor | BackgroundMode::Span => gl::CLAMP_TO_EDGE,
             } as i32;
 
             self.gl.ActiveTexture(gl::TEXTURE0);
@@ -357,8 +521,30 @@ impl Renderer {
                 display_info.adjusted_width(),
                 display_info.adjusted_height(),
             );
-            self.check_error("Failed to resize the openGL viewport")
+            self.check_error("Failed to resize the openGL viewport")?;
         }
+        if let Some(effects) = self.effects.as_mut() {
+            effects
+                .resize(
+                    display_info.adjusted_width(),
+                    display_info.adjusted_height(),
+                )
+                .wrap_err("Failed to resize the textures of the effects")?;
+        }
+        if let Some(shader) = self.shader.as_mut() {
+            let (width, height) = shader_size(display_info);
+            self.current_wallpaper
+                .load_image(&DynamicImage::new_rgba8(width as u32, height as u32), true)?;
+            self.current_wallpaper.stretch();
+            unsafe {
+                shader
+                    .resize(width, height)
+                    .wrap_err("Failed to render the shader again")?;
+                self.gl.UseProgram(self.program);
+                self.check_error("Failed to switch back to the transition program")?;
+            }
+        }
+        Ok(())
     }
 
     #[inline]
@@ -373,11 +559,10 @@ impl Renderer {
 
     #[inline]
     pub fn update_transition(&mut self, transition: Transition, transform: Transform) {
-        match create_program(&self.gl, transition) {
+        match create_transition_program(&self.gl, transition.clone()) {
             Ok(program) => {
-                unsafe {
-                    self.gl.DeleteProgram(self.program);
-                }
+                self.delete_programs();
+                self.programs.0.push((transition, program));
                 // Stop the transition immediately
                 if self.transition_running() {
                     self.transition_finished();
@@ -396,6 +581,41 @@ impl Renderer {
         }
     }
 
+    /// Use the program of `transition` for the next transition, creating it only the first time.
+    /// The uniforms set by [Renderer::set_mode] need to be set again afterwards
+    pub fn switch_transition(
+        &mut self,
+        transition: &Transition,
+        transform: Transform,
+    ) -> Result<()> {
+        let (program, settings_changed) = self.programs.get_or_create(transition, || {
+            create_transition_program(&self.gl, transition.clone())
+                .wrap_err("Failed to create openGL program")
+        })?;
+        if program == self.program && !settings_changed {
+            return Ok(());
+        }
+        unsafe {
+            self.gl.UseProgram(program);
+            self.check_error("Failed to switch openGL program")?;
+            self.program = program;
+            // The settings of the transitions picked randomly can change each time
+            if settings_changed {
+                transition.clone().uniforms()(&self.gl, program)
+                    .wrap_err("Failed to set the settings of the transition")?;
+            }
+            self.set_projection_matrix(transform)
+        }
+    }
+
+    fn delete_programs(&mut self) {
+        for (_, program) in self.programs.0.drain(..) {
+            unsafe {
+                self.gl.DeleteProgram(program);
+            }
+        }
+    }
+
     #[inline]
     pub fn transition_running(&self) -> bool {
         match self.transition_status {
@@ -422,6 +642,94 @@ impl Renderer {
     }
 }
 
+/// Size of the texture a shader renders into, before the transform of the display
+fn shader_size(display_info: &DisplayInfo) -> (i32, i32) {
+    (
+        display_info.scaled_width().max(1),
+        display_info.scaled_height().max(1),
+    )
+}
+
+/// Compute the scale and the translation of the texture for a display spanning the image with
+/// other displays. The image covers the whole canvas like in Center mode, and each display
+/// shows the part of the image in its area.
+fn span_texture_scale(image_ratio: f32, offset: f32, area: SpanArea) -> ([f32; 2], [f32; 2]) {
+    // Portion of the image covering the canvas
+    let (canvas_width, canvas_height) = if image_ratio > area.canvas_ratio {
+        (area.canvas_ratio / image_ratio, 1.0)
+    } else {
+        (1.0, image_ratio / area.canvas_ratio)
+    };
+    let scale = [canvas_width * area.width, canvas_height * area.height];
+    // Where the area of this display starts inside the image
+    let start = [
+        offset * (1.0 - canvas_width) + canvas_width * area.x,
+        offset * (1.0 - canvas_height) + canvas_height * area.y,
+    ];
+    // The shader scales the coordinates around texture_offset, compensate for it
+    let translation = [
+        start[0] - offset * (1.0 - scale[0]),
+        start[1] - offset * (1.0 - scale[1]),
+    ];
+    (scale, translation)
+}
+
+/// Create the program for `transition`. A custom shader that cannot be used is reported like a
+/// configuration error and replaced by the fade transition
+fn create_transition_program(gl: &gl::Gl, transition: Transition) -> Result<gl::types::GLuint> {
+    let Transition::Custom { path, .. } = &transition else {
+        return create_program(gl, transition);
+    };
+    match create_program(gl, transition.clone()) {
+        Ok(program) => Ok(program),
+        Err(err) => {
+            error!(
+                "{:?}",
+                err.wrap_err(format!(
+                    "The shader {} for attribute {} cannot be used",
+                    path.to_string_lossy().italic().yellow(),
+                    "transition".bold().italic().blue(),
+                ))
+                .with_suggestion(|| {
+                    format!(
+                        "Fix the shader, the {} transition is used in the meantime",
+                        "fade".bold().italic().blue(),
+                    )
+                })
+            );
+            create_program(gl, Transition::Fade {})
+        }
+    }
+}
+
+/// The programs of the transitions, one for each shader, with the settings last set in it
+struct TransitionPrograms(Vec<(Transition, gl::types::GLuint)>);
+
+impl TransitionPrograms {
+    /// Return the program of the shader of `transition`, created by `create` the first time, and
+    /// whether the settings of `transition` have to be set in it because they changed
+    fn get_or_create(
+        &mut self,
+        transition: &Transition,
+        create: impl FnOnce() -> Result<gl::types::GLuint>,
+    ) -> Result<(gl::types::GLuint, bool)> {
+        match self.0.iter_mut().find(|(t, _)| t.same_shader(transition)) {
+            Some((t, program)) => {
+                let settings_changed = t != transition;
+                if settings_changed {
+                    *t = transition.clone();
+                }
+                Ok((*program, settings_changed))
+            }
+            None => {
+                let program = create()?;
+                self.0.push((transition.clone(), program));
+                Ok((program, false))
+            }
+        }
+    }
+}
+
 fn create_program(gl: &gl::Gl, transition: Transition) -> Result<gl::types::GLuint> {
     unsafe {
         let program = gl.CreateProgram();
@@ -429,7 +737,7 @@ fn create_program(gl: &gl::Gl, transition: Transition) -> Result<gl::types::GLui
 
         let vertex_shader = create_shader(gl, gl::VERTEX_SHADER, &[VERTEX_SHADER_SOURCE.as_ptr()])
             .wrap_err("Failed to create vertex shader")?;
-        let (uniform_callback, shader) = transition.clone().shader();
+        let (uniform_callback, shader) = transition.clone().shader()?;
         let fragment_shader = create_shader(
             gl,
             gl::FRAGMENT_SHADER,
@@ -538,7 +846,159 @@ impl Drop for Renderer {
         unsafe {
             self.gl.DeleteBuffers(1, &self.eab);
             self.gl.DeleteBuffers(1, &self.vbo);
-            self.gl.DeleteProgram(self.program);
         }
+        self.effects = None;
+        self.shader = None;
+        self.delete_programs();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::{SerializedWallpaperInfo, TransitionSetting},
+        wallpaper_info::{Color, Gradient},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_background_image() {
+        let color = background_image(&Background::Color(Color([30, 30, 46])), 1920, 1080);
+        assert_eq!((color.width(), color.height()), (1, 1));
+        assert_eq!(color.get_pixel(0, 0).0, [30, 30, 46, 255]);
+
+        let gradient = Gradient {
+            from: Color([0, 0, 0]),
+            to: Color([255, 255, 255]),
+            angle: 90.0,
+        };
+        let image = background_image(&Background::Gradient(gradient), 100, 50);
+        assert_eq!((image.width(), image.height()), (100, 50));
+        // From left to right
+        assert!(image.get_pixel(0, 0).0[0] < 5);
+        assert!(image.get_pixel(99, 49).0[0] > 250);
+        assert_eq!(image.get_pixel(0, 0), image.get_pixel(0, 49));
+        // The default angle goes from top to bottom
+        let image = background_image(
+            &Background::Gradient(Gradient {
+                angle: Gradient::default_angle(),
+                ..gradient
+            }),
+            100,
+            50,
+        );
+        assert!(image.get_pixel(0, 0).0[0] < 5);
+        assert!(image.get_pixel(0, 49).0[0] > 250);
+        assert_eq!(image.get_pixel(0, 0), image.get_pixel(99, 0));
+    }
+
+    /// Texture coordinates of the point `uv` of the display, computed like the fragment shader
+    fn texture_coords(image_ratio: f32, offset: f32, area: SpanArea, uv: [f32; 2]) -> [f32; 2] {
+        let (scale, translation) = span_texture_scale(image_ratio, offset, area);
+        [0, 1].map(|i| (uv[i] - offset) * scale[i] + offset + translation[i])
+    }
+
+    fn assert_close(a: [f32; 2], b: [f32; 2]) {
+        assert!(
+            (a[0] - b[0]).abs() < 1e-5 && (a[1] - b[1]).abs() < 1e-5,
+            "{a:?} != {b:?}"
+        );
+    }
+
+    #[test]
+    fn test_span_texture_scale() {
+        let area = |x, width, height, canvas_ratio| SpanArea {
+            x,
+            y: 0.0,
+            width,
+            height,
+            canvas_ratio,
+        };
+        // Two 1920x1080 displays side by side
+        let left = area(0.0, 0.5, 1.0, 32.0 / 9.0);
+        let right = area(0.5, 0.5, 1.0, 32.0 / 9.0);
+        // A 1920x1080 display at scale 1 next to a 3840x2160 display at scale 2 rotated by 90
+        // degrees, their logical sizes are 1920x1080 and 1080x1920. The texture coordinates are
+        // in the logical orientation, the projection matrix applies the transform
+        let landscape = area(0.0, 0.64, 0.5625, 3000.0 / 1920.0);
+        let portrait = area(0.64, 0.36, 1.0, 3000.0 / 1920.0);
+
+        // (left display, right display, image ratio, offset)
+        let cases = [
+            (left, right, 16.0 / 9.0, 0.5),
+            (left, right, 4.0, 0.5),
+            (left, right, 4.0, 0.2),
+            (landscape, portrait, 16.0 / 9.0, 0.5),
+            (landscape, portrait, 21.0 / 9.0, 0.2),
+            (landscape, portrait, 1.0, 0.8),
+        ];
+        for (a, b, image_ratio, offset) in cases {
+            // The right edge of the first display meets the left edge of the second one, along
+            // the height they share
+            for y in [0.0, 0.25, a.height] {
+                assert_close(
+                    texture_coords(image_ratio, offset, a, [1.0, y / a.height]),
+                    texture_coords(image_ratio, offset, b, [0.0, y / b.height]),
+                );
+            }
+            // The canvas covers the image like in Center mode, the offset moves it
+            let start = texture_coords(image_ratio, offset, a, [0.0, 0.0]);
+            let end = texture_coords(image_ratio, offset, b, [1.0, 1.0]);
+            let (width, height) = if image_ratio > a.canvas_ratio {
+                (a.canvas_ratio / image_ratio, 1.0)
+            } else {
+                (1.0, image_ratio / a.canvas_ratio)
+            };
+            assert_close(start, [offset * (1.0 - width), offset * (1.0 - height)]);
+            assert_close([end[0] - start[0], end[1] - start[1]], [width, height]);
+        }
+    }
+
+    #[test]
+    fn test_transition_programs() {
+        let info: SerializedWallpaperInfo =
+            toml::from_str("transition.random = [{ doom = { bars = [10, 1000] } }, \"fade\"]")
+                .unwrap();
+        let Some(TransitionSetting::Random(transitions)) = info.transition else {
+            panic!("unexpected transition {:?}", info.transition);
+        };
+        let mut programs = TransitionPrograms(Vec::new());
+        let mut created = 0;
+        let mut create = || {
+            created += 1;
+            Ok(created)
+        };
+
+        // The ranged settings change at each sample, the program is compiled only once
+        let (program, _) = programs
+            .get_or_create(&transitions[0].sample(), &mut create)
+            .unwrap();
+        let (same_program, _) = programs
+            .get_or_create(&transitions[0].sample(), &mut create)
+            .unwrap();
+        assert_eq!(program, same_program);
+        assert_eq!(programs.0.len(), 1);
+        let doom = |bars| Transition::Doom {
+            bars: Some(bars),
+            amplitude: None,
+            noise: None,
+            frequency: None,
+            drip_scale: None,
+        };
+        programs.get_or_create(&doom(1), &mut create).unwrap();
+        assert_eq!(
+            programs.get_or_create(&doom(2), &mut create).unwrap(),
+            (program, true)
+        );
+        assert_eq!(
+            programs.get_or_create(&doom(2), &mut create).unwrap(),
+            (program, false)
+        );
+
+        programs
+            .get_or_create(&transitions[1].sample(), &mut create)
+            .unwrap();
+        assert_eq!(programs.0.len(), 2);
     }
 }
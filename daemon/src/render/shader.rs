@@ -1,6 +1,6 @@
 use std::ffi::CStr;
 
-use color_eyre::Result;
+use color_eyre::{eyre::WrapErr, Result};
 
 use crate::gl_check;
 
@@ -52,6 +52,34 @@ pub unsafe fn create_shader(
     }
 }
 
+/// Create a program drawing over the whole framebuffer, without any transformation
+pub unsafe fn create_fullscreen_program(
+    gl: &gl::Gl,
+    fragment_sources: &[*const std::ffi::c_char],
+) -> Result<gl::types::GLuint> {
+    let program = gl.CreateProgram();
+    gl_check!(gl, "Failed to create openGL program");
+    let vertex_shader = create_shader(
+        gl,
+        gl::VERTEX_SHADER,
+        &[FULLSCREEN_VERTEX_SHADER_SOURCE.as_ptr()],
+    )
+    .wrap_err("Failed to create vertex shader")?;
+    let fragment_shader = create_shader(gl, gl::FRAGMENT_SHADER, fragment_sources)
+        .wrap_err("Failed to create fragment shader")?;
+    gl.AttachShader(program, vertex_shader);
+    gl_check!(gl, "Failed to attach vertices shader");
+    gl.AttachShader(program, fragment_shader);
+    gl_check!(gl, "Failed to attach fragment shader");
+    gl.LinkProgram(program);
+    gl_check!(gl, "Failed to link the openGL program");
+    gl.DeleteShader(vertex_shader);
+    gl_check!(gl, "Failed to delete the vertices shader");
+    gl.DeleteShader(fragment_shader);
+    gl_check!(gl, "Failed to delete the fragment shader");
+    Ok(program)
+}
+
 pub const VERTEX_SHADER_SOURCE: &CStr = c"
 #version 300 es
 precision mediump float;
@@ -77,6 +105,8 @@ in vec2 v_texcoord;
 
 uniform vec2 textureScale;
 uniform vec2 prevTextureScale;
+uniform vec2 textureTranslation;
+uniform vec2 prevTextureTranslation;
 uniform sampler2D u_prev_texture;
 uniform sampler2D u_texture;
 
@@ -87,15 +117,29 @@ uniform float texture_offset;
 vec4 transition(vec2);
 
 vec4 getFromColor(vec2 uv) {
-    uv = (uv - texture_offset) * prevTextureScale + (texture_offset);
+    uv = (uv - texture_offset) * prevTextureScale + (texture_offset) + prevTextureTranslation;
     return texture(u_prev_texture, uv);
 }
 
 vec4 getToColor(vec2 uv) {
-    uv = (uv - texture_offset) * textureScale + (texture_offset);
+    uv = (uv - texture_offset) * textureScale + (texture_offset) + textureTranslation;
     return texture(u_texture, uv);
 }
 
 void main() {
     FragColor = transition(v_texcoord);
 }";
+
+/// Used to draw over a texture covering the whole framebuffer, like the ones of the effects
+pub const FULLSCREEN_VERTEX_SHADER_SOURCE: &CStr = c"
+#version 300 es
+precision mediump float;
+
+layout (location = 0) in vec2 aPosition;
+
+out vec2 v_texcoord;
+
+void main() {
+    gl_Position = vec4(aPosition, 1.0, 1.0);
+    v_texcoord = aPosition * 0.5 + 0.5;
+}";
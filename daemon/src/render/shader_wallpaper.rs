@@ -0,0 +1,288 @@
+use std::{
+    ffi::{CStr, CString},
+    fs,
+    path::Path,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use color_eyre::{
+    eyre::{ensure, WrapErr},
+    Result,
+};
+
+use crate::gl_check;
+
+use super::{gl, shader::create_fullscreen_program};
+
+/// Frames per second rendered when `animation-max-fps` is not set
+pub const DEFAULT_MAX_FPS: u32 = 30;
+
+/// Return true if `path` is a Shadertoy-style shader instead of an image
+pub fn is_shader(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("glsl") || ext.eq_ignore_ascii_case("frag"))
+}
+
+/// Render a shader defining a `mainImage` function, like the ones on Shadertoy, into the texture
+/// of the current wallpaper
+pub struct ShaderWallpaper {
+    gl: Rc<gl::Gl>,
+    program: gl::types::GLuint,
+    framebuffer: gl::types::GLuint,
+    resolution: gl::types::GLint,
+    time: gl::types::GLint,
+    frame: gl::types::GLint,
+    width: i32,
+    height: i32,
+    /// The uniforms of the last frame rendered, used to render it again after a resize
+    last_frame: (f32, i32),
+}
+
+impl ShaderWallpaper {
+    /// Compile the shader at `path`, it can be attached to a texture afterwards
+    pub unsafe fn new(gl: Rc<gl::Gl>, path: &Path) -> Result<Self> {
+        let source =
+            fs::read(path).wrap_err_with(|| format!("Failed to read the shader {path:?}"))?;
+        let source = CString::new(source).wrap_err_with(|| format!("Invalid shader {path:?}"))?;
+        let program = create_fullscreen_program(
+            &gl,
+            &[
+                SHADER_PREFIX.as_ptr(),
+                source.as_ptr(),
+                SHADER_MAIN.as_ptr(),
+            ],
+        )
+        .wrap_err_with(|| format!("Failed to compile the shader {path:?}"))?;
+        let mut shader = Self {
+            gl,
+            program,
+            framebuffer: 0,
+            resolution: -1,
+            time: -1,
+            frame: -1,
+            width: 0,
+            height: 0,
+            last_frame: (0.0, 0),
+        };
+        let mut status = 0;
+        shader
+            .gl
+            .GetProgramiv(program, gl::LINK_STATUS, &mut status);
+        gl_check!(shader.gl, "Failed to get the link status of the shader");
+        ensure!(status != 0, "Failed to link the shader {path:?}");
+        // The uniforms not used by the shader are optimized out and have no location
+        shader.resolution = shader
+            .gl
+            .GetUniformLocation(program, c"iResolution".as_ptr());
+        shader.time = shader.gl.GetUniformLocation(program, c"iTime".as_ptr());
+        shader.frame = shader.gl.GetUniformLocation(program, c"iFrame".as_ptr());
+        gl_check!(
+            shader.gl,
+            "Failed to get the uniform locations of the shader"
+        );
+        shader.gl.GenFramebuffers(1, &mut shader.framebuffer);
+        gl_check!(
+            shader.gl,
+            "Failed to generate the framebuffer of the shader"
+        );
+        Ok(shader)
+    }
+
+    /// Render into `texture`, of `width`x`height`
+    pub unsafe fn attach(
+        &mut self,
+        texture: gl::types::GLuint,
+        width: i32,
+        height: i32,
+    ) -> Result<()> {
+        self.gl.BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+        gl_check!(self.gl, "Failed to bind the framebuffer of the shader");
+        self.gl.FramebufferTexture2D(
+            gl::FRAMEBUFFER,
+            gl::COLOR_ATTACHMENT0,
+            gl::TEXTURE_2D,
+            texture,
+            0,
+        );
+        gl_check!(self.gl, "Failed to attach the texture to the framebuffer");
+        let status = self.gl.CheckFramebufferStatus(gl::FRAMEBUFFER);
+        self.gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gl_check!(self.gl, "Failed to unbind the framebuffer");
+        ensure!(
+            status == gl::FRAMEBUFFER_COMPLETE,
+            "The framebuffer is incomplete: {status}"
+        );
+        self.width = width;
+        self.height = height;
+        Ok(())
+    }
+
+    /// Render the frame at `time` seconds. The shader program is in use afterwards
+    pub unsafe fn render(&mut self, time: f32, frame: i32) -> Result<()> {
+        let mut viewport = [0; 4];
+        self.gl.GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+        gl_check!(self.gl, "Failed to get the openGL viewport");
+        self.gl.BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+        gl_check!(self.gl, "Failed to bind the framebuffer of the shader");
+        self.gl.Viewport(0, 0, self.width, self.height);
+        gl_check!(self.gl, "Failed to set the viewport of the shader");
+        self.gl.UseProgram(self.program);
+        gl_check!(self.gl, "Failed to switch to the shader program");
+        if self.resolution >= 0 {
+            self.gl
+                .Uniform3f(self.resolution, self.width as f32, self.height as f32, 1.0);
+        }
+        if self.time >= 0 {
+            self.gl.Uniform1f(self.time, time);
+        }
+        if self.frame >= 0 {
+            self.gl.Uniform1i(self.frame, frame);
+        }
+        gl_check!(self.gl, "Failed to set the uniforms of the shader");
+        self.gl
+            .DrawElements(gl::TRIANGLES, 6, gl::UNSIGNED_INT, std::ptr::null());
+        gl_check!(self.gl, "Failed to draw the shader");
+        self.gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gl_check!(self.gl, "Failed to unbind the framebuffer");
+        self.gl
+            .Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+        gl_check!(self.gl, "Failed to restore the openGL viewport");
+        self.last_frame = (time, frame);
+        Ok(())
+    }
+
+    /// Render the last frame again, after the texture has been resized to `width`x`height`
+    pub unsafe fn resize(&mut self, width: i32, height: i32) -> Result<()> {
+        self.width = width;
+        self.height = height;
+        let (time, frame) = self.last_frame;
+        self.render(time, frame)
+    }
+}
+
+impl Drop for ShaderWallpaper {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.DeleteFramebuffers(1, &self.framebuffer);
+            self.gl.DeleteProgram(self.program);
+        }
+    }
+}
+
+/// Keep the time of a shader wallpaper, which doesn't advance while the display is paused
+pub struct ShaderClock {
+    /// How long the shader played before the last time it has been stopped
+    elapsed: Duration,
+    /// When the shader started playing again
+    playing_since: Instant,
+    /// When the current frame has been rendered
+    rendered_at: Instant,
+    frame: i32,
+    stopped: bool,
+}
+
+impl ShaderClock {
+    /// The first frame has already been rendered at `now`
+    pub fn new(now: Instant) -> Self {
+        Self {
+            elapsed: Duration::ZERO,
+            playing_since: now,
+            rendered_at: now,
+            frame: 0,
+            stopped: false,
+        }
+    }
+
+    /// Move to the next frame if the current one has been shown for `1 / max_fps` seconds.
+    /// Return the time in seconds and the number of the new frame, if any
+    pub fn advance(&mut self, now: Instant, max_fps: u32) -> Option<(f32, i32)> {
+        if self.stopped {
+            return None;
+        }
+        let delay = Duration::from_secs(1) / max_fps.max(1);
+        let due = self.next_due(max_fps);
+        if now < due {
+            return None;
+        }
+        // Keep the pace, unless the frame is very late (e.g. the display was hidden)
+        self.rendered_at = if now - due < delay { due } else { now };
+        self.frame += 1;
+        let time = self.elapsed + (now - self.playing_since);
+        Some((time.as_secs_f32(), self.frame))
+    }
+
+    /// When [ShaderClock::advance] will return the next frame
+    pub fn next_due(&self, max_fps: u32) -> Instant {
+        self.rendered_at + Duration::from_secs(1) / max_fps.max(1)
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Stop the time until [ShaderClock::restart] is called
+    pub fn stop(&mut self, now: Instant) {
+        if !self.stopped {
+            self.elapsed += now - self.playing_since;
+            self.stopped = true;
+        }
+    }
+
+    /// Start playing again from the time it has been stopped at
+    pub fn restart(&mut self, now: Instant) {
+        self.playing_since = now;
+        self.rendered_at = now;
+        self.stopped = false;
+    }
+}
+
+/// Declare the Shadertoy uniforms before the code of the shader
+const SHADER_PREFIX: &CStr = c"#version 300 es
+precision highp float;
+
+uniform vec3 iResolution;
+uniform float iTime;
+uniform int iFrame;
+
+out vec4 wpaperd_FragColor;
+";
+
+/// The texture is drawn upside down, while Shadertoy has the origin in the bottom left corner
+const SHADER_MAIN: &CStr = c"
+void main() {
+    mainImage(wpaperd_FragColor, vec2(gl_FragCoord.x, iResolution.y - gl_FragCoord.y));
+    wpaperd_FragColor.a = 1.0;
+}";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shader_clock() {
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let mut clock = ShaderClock::new(start);
+
+        // 10 frames per second
+        assert_eq!(clock.advance(ms(50), 10), None);
+        assert_eq!(clock.advance(ms(120), 10), Some((0.12, 1)));
+        assert_eq!(clock.next_due(10), ms(200));
+        // The frame has been rendered late, the next one keeps the pace
+        assert_eq!(clock.advance(ms(199), 10), None);
+        assert_eq!(clock.advance(ms(200), 10), Some((0.2, 2)));
+
+        // The time doesn't advance while stopped
+        clock.stop(ms(250));
+        assert_eq!(clock.advance(ms(1000), 10), None);
+        clock.restart(ms(1000));
+        assert_eq!(clock.advance(ms(1050), 10), None);
+        assert_eq!(clock.advance(ms(1100), 10), Some((0.35, 3)));
+
+        assert!(is_shader(Path::new("/tmp/waves.glsl")));
+        assert!(is_shader(Path::new("/tmp/waves.FRAG")));
+        assert!(!is_shader(Path::new("/tmp/waves.png")));
+    }
+}
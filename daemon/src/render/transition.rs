@@ -1,10 +1,19 @@
-use std::ffi::CStr;
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    ffi::{CStr, CString},
+    fs,
+    path::PathBuf,
+};
 
-use color_eyre::{eyre::ensure, Result};
+use color_eyre::{
+    eyre::{ensure, WrapErr},
+    Result,
+};
 use serde::Deserialize;
 //use wpaperd_transitions_proc_macro::Transitions;
 
-use crate::gl_check;
+use crate::{config::tilde_expansion_deserialize, gl_check};
 
 use super::gl;
 
@@ -91,22 +100,78 @@ impl UniformSetter for [f32; 4] {
     }
 }
 
+/// The value of a uniform of a [Transition::Custom] shader, its type depends on the TOML value.
+/// Arrays of numbers are always float vectors
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum UniformValue {
+    Bool(bool),
+    Int(i32),
+    Float(f32),
+    Vec2([f32; 2]),
+    Vec3([f32; 3]),
+    Vec4([f32; 4]),
+}
+
+impl UniformSetter for UniformValue {
+    unsafe fn set_uniform(&self, gl: &gl::Gl, loc: gl::types::GLint) {
+        match self {
+            UniformValue::Bool(value) => value.set_uniform(gl, loc),
+            UniformValue::Int(value) => value.set_uniform(gl, loc),
+            UniformValue::Float(value) => value.set_uniform(gl, loc),
+            UniformValue::Vec2(value) => value.set_uniform(gl, loc),
+            UniformValue::Vec3(value) => value.set_uniform(gl, loc),
+            UniformValue::Vec4(value) => value.set_uniform(gl, loc),
+        }
+    }
+}
+
+fn shader_path_deserialize<'de, D>(deserializer: D) -> Result<PathBuf, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    tilde_expansion_deserialize(deserializer).map(Option::unwrap_or_default)
+}
+
 macro_rules! transition_shader {
     ($enum:ident { $($variant:ident { $($field_name:ident: $field_ty:ty = ($glsl_name:literal, $default_value:expr)),* } => $default_time:expr),* }) => {
         #[derive(Deserialize, Clone, Debug, PartialEq)]
         #[serde(rename_all = "kebab-case", rename_all_fields = "kebab-case", deny_unknown_fields)]
         pub enum $enum {
-            $($variant { $($field_name: Option<$field_ty>),* }),*
+            $($variant { $($field_name: Option<$field_ty>),* }),*,
+            /// A shader compatible with gl-transitions, loaded from `path`
+            Custom {
+                #[serde(deserialize_with = "shader_path_deserialize")]
+                path: PathBuf,
+                #[serde(default)]
+                uniforms: BTreeMap<String, UniformValue>,
+            },
         }
 
         impl $enum {
-            pub fn shader(self) -> (Box<UniformCallback>, &'static CStr) {
+            pub fn shader(self) -> Result<(Box<UniformCallback>, Cow<'static, CStr>)> {
+                let source = self.source()?;
+                Ok((self.uniforms(), source))
+            }
+
+            /// The source of the fragment shader
+            fn source(&self) -> Result<Cow<'static, CStr>> {
+                match self {
+                    $($enum::$variant { .. } => Ok(Cow::Borrowed(include_cstr!(concat!("shaders/", stringify!($variant), ".glsl")))),)*
+                    $enum::Custom { path, .. } => {
+                        let source = fs::read(path)
+                            .wrap_err_with(|| format!("Failed to read the shader {path:?}"))?;
+                        let source = CString::new(source)
+                            .wrap_err_with(|| format!("Invalid shader {path:?}"))?;
+                        Ok(Cow::Owned(source))
+                    }
+                }
+            }
+
+            /// Set the uniforms of the settings in the program of the transition
+            pub fn uniforms(self) -> Box<UniformCallback> {
                 match self {
-                    //$($enum::$variant => (
-                    //    Box::new(|_, _| Ok(())),
-                    //    include_cstr!(concat!("shaders/", stringify!($variant), ".glsl")),
-                    //),)*
-                    $($enum::$variant { $($field_name),* } => (
+                    $($enum::$variant { $($field_name),* } => {
                         #[allow(unused)]
                         Box::new(move |gl, program| {
                             $(
@@ -119,15 +184,29 @@ macro_rules! transition_shader {
                                 }
                             )*
                             Ok(())
-                        }),
-                        include_cstr!(concat!("shaders/", stringify!($variant), ".glsl"))
-                    ),)*
+                        })
+                    },)*
+                    $enum::Custom { path, uniforms } => {
+                        Box::new(move |gl, program| {
+                            for (name, value) in &uniforms {
+                                unsafe {
+                                    let loc = gl.GetUniformLocation(program, format_bytes!(b"{}\0", name.as_bytes()).as_ptr() as *const _);
+                                    gl_check!(gl, format!("Failed to get the uniform location for parameter {name}"));
+                                    ensure!(loc >= 0, "Uniform {name} does not exist in the shader {path:?}");
+                                    value.set_uniform(gl, loc);
+                                    gl_check!(gl, format!("Failed to set the value of the uniform {name}"));
+                                }
+                            }
+                            Ok(())
+                        })
+                    }
                 }
             }
 
             pub const fn default_transition_time(&self) -> u32 {
                 match self {
                     $($enum::$variant { .. } => $default_time,)*
+                    $enum::Custom { .. } => 1000,
                 }
             }
         }
@@ -277,3 +356,15 @@ transition_shader! {
         WindowBlinds {} => 1500
     }
 }
+
+impl Transition {
+    /// Return true if both transitions use the same shader, whatever their settings are
+    pub fn same_shader(&self, other: &Transition) -> bool {
+        match (self, other) {
+            (Transition::Custom { path, .. }, Transition::Custom { path: other, .. }) => {
+                path == other
+            }
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+        }
+    }
+}
@@ -13,10 +13,13 @@ pub struct Wallpaper {
     texture: gl::types::GLuint,
     image_width: u32,
     image_height: u32,
+    /// The image covers the display regardless of the background mode, like a generated
+    /// background
+    stretched: bool,
 }
 
 impl Wallpaper {
-    pub fn new(gl: Rc<gl::Gl>, image: DynamicImage, current: bool) -> Result<Self> {
+    pub fn new(gl: Rc<gl::Gl>, image: &DynamicImage, current: bool) -> Result<Self> {
         let image_width = image.width();
         let image_height = image.height();
         let mut texture = 0;
@@ -46,6 +49,7 @@ impl Wallpaper {
             texture,
             image_width,
             image_height,
+            stretched: false,
         })
     }
 
@@ -58,6 +62,10 @@ impl Wallpaper {
         Ok(())
     }
 
+    pub fn texture(&self) -> gl::types::GLuint {
+        self.texture
+    }
+
     pub fn get_image_height(&self) -> u32 {
         self.image_height
     }
@@ -66,9 +74,19 @@ impl Wallpaper {
         self.image_width
     }
 
-    pub fn load_image(&mut self, image: DynamicImage, current: bool) -> Result<()> {
+    pub fn is_stretched(&self) -> bool {
+        self.stretched
+    }
+
+    /// Stretch the loaded image over the display, until the next one is loaded
+    pub fn stretch(&mut self) {
+        self.stretched = true;
+    }
+
+    pub fn load_image(&mut self, image: &DynamicImage, current: bool) -> Result<()> {
         self.image_width = image.width();
         self.image_height = image.height();
+        self.stretched = false;
 
         unsafe {
             self.gl
@@ -0,0 +1,96 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use color_eyre::{eyre::WrapErr, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{image_picker::ImagePickerState, surface::PauseReason};
+
+/// State of a single output, saved when wpaperd exits so that it can resume where it left off
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct OutputState {
+    #[serde(flatten)]
+    pub image_picker: ImagePickerState,
+    #[serde(default)]
+    pub pause_reason: Option<PauseReason>,
+    /// Time left before the wallpaper gets changed
+    #[serde(default, with = "humantime_serde")]
+    pub duration_left: Option<Duration>,
+}
+
+/// The state of all the outputs, stored in $XDG_STATE_HOME/wpaperd/state.json
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct State {
+    /// The state of each output, indexed by its name. Outputs that are not connected anymore
+    /// are kept, so that they can be restored when they get connected again.
+    outputs: HashMap<String, OutputState>,
+    /// Where the state is saved. The state is not saved when it's None
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl State {
+    pub const FILENAME: &'static str = "state.json";
+
+    /// Load the state from `path`. A missing or invalid file results in an empty state
+    pub fn load(path: PathBuf) -> Self {
+        let res = if path.exists() {
+            fs::read_to_string(&path)
+                .wrap_err_with(|| format!("Failed to read the state file {path:?}"))
+                .and_then(|content| {
+                    serde_json::from_str(&content)
+                        .wrap_err_with(|| format!("Failed to parse the state file {path:?}"))
+                })
+        } else {
+            Ok(Self::default())
+        };
+        let mut state = res.unwrap_or_else(|err| {
+            warn!(
+                "{:?}",
+                err.wrap_err("The previous state will not be restored")
+            );
+            Self::default()
+        });
+        state.path = Some(path);
+        state
+    }
+
+    pub fn get(&self, output: &str) -> Option<&OutputState> {
+        self.outputs.get(output)
+    }
+
+    pub fn update(&mut self, output: &str, state: OutputState) {
+        self.outputs.insert(output.to_string(), state);
+    }
+
+    /// Write the state to disk
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        write_atomically(
+            path,
+            &serde_json::to_vec(self).wrap_err("Failed to serialize wpaperd state")?,
+        )
+    }
+}
+
+/// Write `content` into a temporary file and then move it to `path`, so that a crash
+/// while writing doesn't leave a truncated file behind
+pub fn write_atomically(path: &Path, content: &[u8]) -> Result<()> {
+    let tmp_path = tmp_path(path);
+    fs::write(&tmp_path, content)
+        .wrap_err_with(|| format!("Failed to write the file {tmp_path:?}"))?;
+    fs::rename(&tmp_path, path).wrap_err_with(|| format!("Failed to move {tmp_path:?} to {path:?}"))
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut filename = path.file_name().unwrap_or_default().to_os_string();
+    filename.push(".tmp");
+    path.with_file_name(filename)
+}
@@ -14,6 +14,7 @@ use color_eyre::{
     Result,
 };
 use log::{error, warn};
+use serde::{Deserialize, Serialize};
 use smithay_client_toolkit::{
     reexports::{
         calloop::{
@@ -35,17 +36,21 @@ use smithay_client_toolkit::{
 };
 
 use crate::{
-    display_info::DisplayInfo,
-    image_loader::ImageLoader,
+    animation::AnimationPlayer,
+    display_info::{DisplayInfo, SpanArea},
+    image_filter::ImageFilter,
+    image_loader::{ImageKey, ImageLoader, TargetSize},
     image_picker::{ImagePicker, ImageResult},
-    render::EglContext,
+    render::{is_shader, EglContext, ShaderClock, DEFAULT_MAX_FPS},
+    state::OutputState,
     wallpaper_groups::WallpaperGroups,
-    wallpaper_info::{Sorting, WallpaperInfo},
+    wallpaper_info::{Background, BackgroundMode, WallpaperInfo},
     wpaperd::Wpaperd,
 };
 
 /// Reason for pausing automatic wallpaper cycling
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum PauseReason {
     /// Explicit pause via `wpaperctl pause`
     User,
@@ -79,6 +84,22 @@ pub struct Surface {
     window_drawn: bool,
     pub loading_image: Option<ImageResult>,
     loading_image_tries: u8,
+    /// The next image, being decoded before the timer expires
+    prefetching: Option<ImageKey>,
+    /// Size and color profile the current image has been decoded for
+    decoded: Option<ImageKey>,
+    /// Set when the current image is animated
+    animation: Option<AnimationPlayer>,
+    /// Set when the current wallpaper is a shader
+    shader: Option<ShaderClock>,
+    /// The color or gradient shown in place of an image
+    background: Option<Background>,
+    /// Set by `wpaperctl set-color`, shown until the next or previous wallpaper is requested
+    forced_background: Option<Background>,
+    /// Index of the last transition picked from [WallpaperInfo::random_transitions]
+    random_transition: Option<usize>,
+    /// Time of the last frame callback handled
+    last_frame_time: Option<u32>,
     /// Determines whether we should skip the next transition. Used to skip
     /// the first transition when starting up.
     ///
@@ -95,6 +116,13 @@ pub struct Surface {
     /// invalid_size (error 2 on wl_surface).  We therefore defer the set_buffer_scale call
     /// until draw() has just swapped a fresh buffer at the correct physical dimensions.
     pending_scale: Option<i32>,
+    /// Time left on the timer in the previous run, used when adding the first timer
+    restored_duration: Option<Duration>,
+    /// The state returned by [Surface::state] changed since the last time it has been saved
+    state_changed: bool,
+    event_loop_handle: LoopHandle<'static, Wpaperd>,
+    /// Wakes the surface up when the next frame of the animation or the shader is due
+    frame_timer: Option<RegistrationToken>,
 }
 
 impl Surface {
@@ -110,13 +138,29 @@ impl Surface {
         // Commit the surface
         wl_surface.commit();
 
-        let image_picker = ImagePicker::new(
+        let mut image_picker = ImagePicker::new(
             &wallpaper_info,
             &wl_surface,
             wpaperd.filelist_cache.clone(),
             wpaperd.wallpaper_groups.clone(),
         );
 
+        // Resume from where we left off in the previous run
+        let (pause_reason, restored_duration) = match wpaperd.state.get(&display_info.name) {
+            Some(state) => {
+                image_picker.restore_state(state.image_picker.clone());
+                (
+                    state.pause_reason,
+                    // The duration might have been changed in the meantime
+                    state
+                        .duration_left
+                        .zip(wallpaper_info.duration)
+                        .map(|(left, duration)| left.min(duration)),
+                )
+            }
+            None => (None, None),
+        };
+
         let first_transition = !wallpaper_info.initial_transition;
 
         let context = match EglContext::new(
@@ -148,13 +192,25 @@ impl Surface {
             event_source: EventSource::NotSet,
             wallpaper_info,
             window_drawn: false,
-            pause_reason: None,
+            pause_reason,
             image_loader: wpaperd.image_loader.clone(),
             loading_image: None,
             loading_image_tries: 0,
+            prefetching: None,
+            decoded: None,
+            animation: None,
+            shader: None,
+            background: None,
+            forced_background: None,
+            random_transition: None,
+            last_frame_time: None,
             skip_next_transition: first_transition,
             symlink_dir,
             pending_scale: None,
+            restored_duration,
+            state_changed: false,
+            event_loop_handle: wpaperd.event_loop_handle.clone(),
+            frame_timer: None,
         };
 
         // Start loading the wallpaper as soon as possible (i.e. surface creation)
@@ -180,6 +236,29 @@ impl Surface {
         let adjusted_height = self.display_info.adjusted_height();
         let scale_pending = self.pending_scale.is_some();
 
+        let user_paused = self.pause_reason == Some(PauseReason::User);
+        let animation_playing = match self.animation.as_mut() {
+            // The animation stops while the display is paused
+            Some(player) if user_paused => {
+                player.stopped = true;
+                false
+            }
+            Some(_) => true,
+            None => false,
+        };
+        // Same for the shaders
+        let shader_playing = match self.shader.as_mut() {
+            Some(clock) if user_paused => {
+                clock.stop(Instant::now());
+                false
+            }
+            Some(_) => true,
+            None => false,
+        };
+        if animation_playing || shader_playing {
+            self.schedule_next_frame(qh);
+        }
+
         // Use the correct context before drawing
         let context = self.get_context()?;
         context
@@ -227,6 +306,153 @@ impl Surface {
         Ok(())
     }
 
+    /// When the next frame of the animation or the shader is due
+    fn next_frame_due(&self) -> Option<Instant> {
+        let max_fps = self.wallpaper_info.animation_max_fps;
+        match (&self.animation, &self.shader) {
+            (Some(player), _) => {
+                Some(player.next_due(self.wallpaper_info.animation_speed, max_fps))
+            }
+            (None, Some(clock)) => Some(clock.next_due(max_fps.unwrap_or(DEFAULT_MAX_FPS))),
+            (None, None) => None,
+        }
+    }
+
+    /// Add a timer that shows the next frame of the animation or the shader once it's due,
+    /// instead of drawing the surface again on every frame of the display
+    fn schedule_next_frame(&mut self, qh: &QueueHandle<Wpaperd>) {
+        if self.frame_timer.is_some() {
+            return;
+        }
+        let Some(due) = self.next_frame_due() else {
+            return;
+        };
+
+        let name = self.name().to_owned();
+        let qh = qh.clone();
+        let registration_token = self
+            .event_loop_handle
+            .insert_source(
+                Timer::from_deadline(due),
+                move |_deadline, _: &mut (), wpaperd: &mut Wpaperd| {
+                    let Some(surface) = wpaperd.surface_from_name(&name) else {
+                        return TimeoutAction::Drop;
+                    };
+                    match surface.next_frame(&qh) {
+                        Some(due) => TimeoutAction::ToInstant(due),
+                        None => {
+                            surface.frame_timer = None;
+                            TimeoutAction::Drop
+                        }
+                    }
+                },
+            )
+            .expect("Failed to insert event source!");
+        self.frame_timer = Some(registration_token);
+    }
+
+    /// Prepare the next frame of the animation or the shader and draw it on the next frame of the
+    /// display. Return when to try again if it isn't due yet
+    fn next_frame(&mut self, qh: &QueueHandle<Wpaperd>) -> Option<Instant> {
+        // Let draw stop the animation and the shader
+        if self.pause_reason == Some(PauseReason::User) {
+            self.queue_draw(qh);
+            return None;
+        }
+        let res = self
+            .advance_animation()
+            .wrap_err("Failed to load the next frame of the animation")
+            .and_then(|advanced| {
+                let rendered = self
+                    .advance_shader()
+                    .wrap_err("Failed to render the next frame of the shader")?;
+                Ok(advanced || rendered)
+            });
+        match res {
+            Ok(true) => {
+                self.queue_draw(qh);
+                None
+            }
+            Ok(false) => self.next_frame_due(),
+            Err(err) => {
+                error!(
+                    "{:?}",
+                    err.wrap_err(format!("Failed to draw on display {}", self.name()))
+                );
+                self.context = None;
+                self.queue_draw(qh);
+                None
+            }
+        }
+    }
+
+    /// Load the next frame of the animation into the texture, if it's time to show it.
+    /// Return true if a new frame has been loaded
+    fn advance_animation(&mut self) -> Result<bool> {
+        let Some(frame) = self.animation.as_mut().and_then(|player| {
+            player.advance(
+                Instant::now(),
+                self.wallpaper_info.animation_speed,
+                self.wallpaper_info.animation_max_fps,
+            )
+        }) else {
+            return Ok(false);
+        };
+        let context = self.get_context()?;
+        context
+            .make_current()
+            .wrap_err("Failed to switch EGL context")?;
+        context.renderer.load_frame(&frame)?;
+        Ok(true)
+    }
+
+    /// Render the next frame of the shader, if it's time to show it.
+    /// Return true if a new frame has been rendered
+    fn advance_shader(&mut self) -> Result<bool> {
+        let max_fps = self
+            .wallpaper_info
+            .animation_max_fps
+            .unwrap_or(DEFAULT_MAX_FPS);
+        let Some((time, frame)) = self
+            .shader
+            .as_mut()
+            .and_then(|clock| clock.advance(Instant::now(), max_fps))
+        else {
+            return Ok(false);
+        };
+        let context = self.get_context()?;
+        context
+            .make_current()
+            .wrap_err("Failed to switch EGL context")?;
+        context.renderer.render_shader_frame(time, frame)?;
+        Ok(true)
+    }
+
+    /// Start playing the animation or the shader again once the display is resumed
+    pub fn play_animation(&mut self, qh: &QueueHandle<Wpaperd>) {
+        if self.pause_reason == Some(PauseReason::User) {
+            return;
+        }
+        if let Some(player) = self.animation.as_mut().filter(|player| player.stopped) {
+            player.restart(Instant::now());
+            self.queue_draw(qh);
+        }
+        if let Some(clock) = self.shader.as_mut().filter(|clock| clock.is_stopped()) {
+            clock.restart(Instant::now());
+            self.queue_draw(qh);
+        }
+    }
+
+    /// Called when a frame callback is done. Return false if a callback for the same frame has
+    /// already been handled, so that the surface is drawn once per frame
+    pub fn new_frame(&mut self, time: u32) -> bool {
+        if self.last_frame_time == Some(time) {
+            return false;
+        }
+        self.last_frame_time = Some(time);
+        true
+    }
+
     pub fn try_drawing(&mut self, qh: &QueueHandle<Wpaperd>) {
         match self.draw(qh) {
             Ok(_) => {}
@@ -252,9 +478,21 @@ impl Surface {
     pub fn load_wallpaper(&mut self) -> Result<bool> {
         // If we were not already trying to load an image
         if self.loading_image.is_none() {
+            if self.image_picker.detour_ended() {
+                self.forced_background = None;
+            }
+            if let Some(background) = self.forced_background.or(self.wallpaper_info.background) {
+                return self.load_background(background);
+            }
+            // Show the image again in place of the background
+            if self.background.is_some() {
+                self.image_picker.reload();
+            }
+            let filter = ImageFilter::new(&self.wallpaper_info, &self.display_info);
             if let Some(item) = self.image_picker.get_image_from_path(
                 &self.wallpaper_info.path,
                 &self.wallpaper_info.recursive.clone(),
+                &filter,
             ) {
                 if self.image_picker.current_image() == *item.path()
                     && !self.image_picker.is_reloading()
@@ -262,6 +500,18 @@ impl Surface {
                     self.image_picker.clear_first_action();
                     return Ok(true);
                 }
+                if is_shader(item.path()) {
+                    return self.load_shader(item);
+                }
+                // Another image has been requested in the meantime, or the size of the display
+                // changed
+                if let Some(prefetching) = self.prefetching.take() {
+                    if prefetching != self.image_key(item.path().to_path_buf()) {
+                        self.image_loader
+                            .borrow_mut()
+                            .cancel(&prefetching, &self.display_info.name);
+                    }
+                }
                 self.loading_image = Some(item);
                 if self.get_context()?.renderer.transition_running() {
                     // A new image is ready, load it and end the current transition abruptly
@@ -280,13 +530,19 @@ impl Surface {
             .expect("loading image to be set")
             .clone();
         let image_path = loading.path().to_path_buf();
+        let key = self.image_key(image_path.clone());
 
         let res = self
             .image_loader
             .borrow_mut()
-            .background_load(image_path.clone(), self.name().to_owned());
+            .background_load(key.clone(), self.name().to_owned());
         match res {
-            crate::image_loader::ImageLoaderStatus::Loaded(data) => {
+            crate::image_loader::ImageLoaderStatus::Loaded(decoded) => {
+                self.decoded = Some(key);
+                if let Some(entry) = decoded.entry.clone() {
+                    self.image_picker
+                        .image_decoded(&image_path, decoded.average_color, entry);
+                }
                 // Exec Script on wallpaper change
                 if self.wallpaper_info.exec.is_some() {
                     self.run_exec_script(&self.wallpaper_info, image_path.clone());
@@ -297,11 +553,17 @@ impl Surface {
                 self.context
                     .as_mut()
                     .ok_or_else(|| eyre!("EGL context is not available"))?
-                    .load_wallpaper(data.into(), background_mode, offset, &self.display_info)?;
-
-                if self.image_picker.is_reloading() {
+                    .load_wallpaper(&decoded.image, background_mode, offset, &self.display_info)?;
+                self.animation = decoded
+                    .animation
+                    .map(|animation| AnimationPlayer::new(animation, Instant::now()));
+                self.shader = None;
+
+                let replaces_background = self.background.take().is_some();
+                if self.image_picker.is_reloading() && !replaces_background {
                     self.image_picker.reloaded();
                 } else {
+                    self.image_picker.reloaded();
                     self.setup_drawing_image(loading);
                 }
                 // Restart the counter
@@ -318,6 +580,7 @@ impl Surface {
                 self.loading_image_tries += 1;
                 // The image we were trying to load failed
                 self.loading_image = None;
+                self.image_picker.image_failed(image_path);
                 // If we have tried too many times, stop
                 if self.loading_image_tries != 5 {
                     return self.load_wallpaper();
@@ -327,6 +590,138 @@ impl Surface {
         }
     }
 
+    /// Generate `background` and show it in place of the current wallpaper
+    fn load_background(&mut self, background: Background) -> Result<bool> {
+        // Nothing needs to be picked or decoded
+        self.image_picker.clear_first_action();
+        if self.background == Some(background) && !self.image_picker.is_reloading() {
+            return Ok(true);
+        }
+        if let Some(prefetching) = self.prefetching.take() {
+            self.image_loader
+                .borrow_mut()
+                .cancel(&prefetching, &self.display_info.name);
+        }
+        let background_mode = self.wallpaper_info.mode;
+        let offset = self.wallpaper_info.offset;
+        let context = self
+            .context
+            .as_mut()
+            .ok_or_else(|| eyre!("EGL context is not available"))?;
+        if context.renderer.transition_running() {
+            context.renderer.transition_finished();
+        }
+        context.load_background(&background, background_mode, offset, &self.display_info)?;
+        self.image_picker.reloaded();
+        self.decoded = None;
+        self.animation = None;
+        self.shader = None;
+        // The background is only generated again when reloading
+        if self.background.replace(background) != Some(background) {
+            self.start_transition();
+            self.state_changed = true;
+        }
+        Ok(true)
+    }
+
+    /// Compile the shader picked and start rendering it in place of the current wallpaper
+    fn load_shader(&mut self, shader: ImageResult) -> Result<bool> {
+        // Nothing needs to be decoded
+        self.image_picker.clear_first_action();
+        if let Some(prefetching) = self.prefetching.take() {
+            self.image_loader
+                .borrow_mut()
+                .cancel(&prefetching, &self.display_info.name);
+        }
+        let path = shader.path().to_path_buf();
+        let background_mode = self.wallpaper_info.mode;
+        let offset = self.wallpaper_info.offset;
+        let context = self
+            .context
+            .as_mut()
+            .ok_or_else(|| eyre!("EGL context is not available"))?;
+        if context.renderer.transition_running() {
+            context.renderer.transition_finished();
+        }
+        if let Err(err) = context.load_shader(&path, background_mode, offset, &self.display_info) {
+            self.image_picker.reloaded();
+            self.image_picker.image_failed(path);
+            return Err(err);
+        }
+        if self.wallpaper_info.exec.is_some() {
+            self.run_exec_script(&self.wallpaper_info, path);
+        }
+        self.decoded = None;
+        self.animation = None;
+        self.shader = Some(ShaderClock::new(Instant::now()));
+        let replaces_background = self.background.take().is_some();
+        if self.image_picker.is_reloading() && !replaces_background {
+            self.image_picker.reloaded();
+        } else {
+            self.image_picker.reloaded();
+            self.setup_drawing_image(shader);
+        }
+        Ok(true)
+    }
+
+    /// Start decoding the image that will be shown when the timer expires
+    fn prefetch_next_image(&mut self) {
+        if self.loading_image.is_some()
+            || self.forced_background.is_some()
+            || self.wallpaper_info.background.is_some()
+        {
+            return;
+        }
+        let filter = ImageFilter::new(&self.wallpaper_info, &self.display_info);
+        let Some(next) = self.image_picker.prepare_next_image(
+            &self.wallpaper_info.path,
+            &self.wallpaper_info.recursive,
+            &filter,
+        ) else {
+            return;
+        };
+        let next = self.image_key(next);
+        if self.prefetching.as_ref() == Some(&next) {
+            return;
+        }
+        let mut image_loader = self.image_loader.borrow_mut();
+        if let Some(prefetching) = self.prefetching.take() {
+            image_loader.cancel(&prefetching, &self.display_info.name);
+        }
+        image_loader.prefetch(next.clone(), self.display_info.name.clone());
+        self.prefetching = Some(next);
+    }
+
+    /// The image at `path` decoded for the size of the display, depending on the background
+    /// mode, and for its color profile
+    fn image_key(&self, path: PathBuf) -> ImageKey {
+        ImageKey {
+            path,
+            size: TargetSize::new(self.wallpaper_info.mode, &self.display_info),
+            color_profile: self.wallpaper_info.color_profile.clone(),
+        }
+    }
+
+    /// Decode the current image again if it has been decoded for a different size or color
+    /// profile
+    fn check_decoded_image(&mut self, qh: &QueueHandle<Wpaperd>) {
+        if self.loading_image.is_some() || self.context.is_none() {
+            return;
+        }
+        // A gradient is generated for the size of the display
+        if self.background.is_none()
+            && self
+                .decoded
+                .as_ref()
+                .map_or(true, |key| *key == self.image_key(key.path.clone()))
+        {
+            return;
+        }
+        self.image_picker.reload();
+        self.load_new_wallpaper();
+        self.queue_draw(qh);
+    }
+
     // Execute bash script function.
     // Provides bash script with name of display and path to wallpaper as arguments
     pub fn run_exec_script(&self, wallpaper_info: &WallpaperInfo, image_path: PathBuf) {
@@ -352,22 +747,59 @@ impl Surface {
     }
 
     pub fn setup_drawing_image(&mut self, result: ImageResult) {
+        self.update_wallpaper_link(result.path());
+        self.image_picker.update_current_image(result);
+        self.state_changed = true;
+        self.start_transition();
+        // Update the instant where we have drawn the image
+        if let EventSource::Running(registration_token, duration, _) = self.event_source {
+            self.event_source = EventSource::Running(registration_token, duration, Instant::now());
+        }
+    }
+
+    /// Start the transition to the wallpaper that has just been loaded
+    fn start_transition(&mut self) {
         let transition_time = if self.skip_next_transition {
             self.skip_next_transition = false;
             0
-        } else {
+        } else if self.wallpaper_info.random_transitions.is_empty() {
             self.wallpaper_info.transition_time
+        } else {
+            self.switch_random_transition().unwrap_or_else(|err| {
+                error!(
+                    "{:?}",
+                    err.wrap_err(format!(
+                        "Failed to switch transition for display {}",
+                        self.name()
+                    ))
+                );
+                self.wallpaper_info.transition_time
+            })
         };
-
-        self.update_wallpaper_link(result.path());
-        self.image_picker.update_current_image(result);
         if let Ok(context) = self.get_context() {
             context.renderer.start_transition(transition_time);
         }
-        // Update the instant where we have drawn the image
-        if let EventSource::Running(registration_token, duration, _) = self.event_source {
-            self.event_source = EventSource::Running(registration_token, duration, Instant::now());
-        }
+    }
+
+    /// Use a random transition, different from the last one, and return its time
+    fn switch_random_transition(&mut self) -> Result<u32> {
+        let transitions = &self.wallpaper_info.random_transitions;
+        let index = match self.random_transition {
+            Some(last) if transitions.len() > 1 => {
+                (last + 1 + fastrand::usize(..transitions.len() - 1)) % transitions.len()
+            }
+            _ => fastrand::usize(..transitions.len()),
+        };
+        let (transition, transition_time) = &transitions[index];
+        let (transition, transition_time) = (transition.sample(), *transition_time);
+        self.random_transition = Some(index);
+        let background_mode = self.wallpaper_info.mode;
+        let offset = self.wallpaper_info.offset;
+        self.context
+            .as_mut()
+            .ok_or_else(|| eyre!("EGL context is not available"))?
+            .switch_transition(&transition, background_mode, offset, &self.display_info)?;
+        Ok(transition_time)
     }
 
     pub fn name(&self) -> &str {
@@ -416,6 +848,7 @@ impl Surface {
             {
                 error!("{err:?}");
             }
+            self.check_decoded_image(qh);
         }
     }
 
@@ -449,6 +882,7 @@ impl Surface {
             {
                 error!("{err:?}");
             }
+            self.check_decoded_image(qh);
         }
     }
 
@@ -466,9 +900,44 @@ impl Surface {
             }) {
                 error!("{err:?}");
             }
+            self.check_decoded_image(qh);
         }
     }
 
+    /// Return the group of this display if it's spanning its wallpaper with the other displays
+    /// of the same group
+    pub fn span_group(&self) -> Option<u8> {
+        match self.wallpaper_info.mode {
+            BackgroundMode::Span => self.wallpaper_info.group,
+            _ => None,
+        }
+    }
+
+    pub fn change_span_area(&mut self, span_area: Option<SpanArea>, qh: &QueueHandle<Wpaperd>) {
+        if !self.display_info.change_span_area(span_area) {
+            return;
+        }
+        let Some(context) = self.context.as_mut() else {
+            // The area will be used once the context has been created
+            return;
+        };
+        if let Err(err) = context
+            .make_current()
+            .and_then(|_| {
+                context.renderer.set_mode(
+                    self.wallpaper_info.mode,
+                    self.wallpaper_info.offset,
+                    &self.display_info,
+                )
+            })
+            .wrap_err_with(|| format!("Failed to change the span area for display {}", self.name()))
+        {
+            error!("{err:?}");
+        }
+        self.queue_draw(qh);
+        self.check_decoded_image(qh);
+    }
+
     /// Check that the dimensions are valid
     pub fn is_configured(&self) -> bool {
         self.display_info.is_configured()
@@ -510,8 +979,8 @@ impl Surface {
             // ask the image_picker to pick a new a image
             self.image_picker.next_image();
             self.load_new_wallpaper();
-        } else if let Some(Sorting::GroupedRandom { .. }) = self.wallpaper_info.sorting {
-            // Always queue draw to load changes (needed for GroupedRandom)
+        } else if self.wallpaper_info.group.is_some() {
+            // Always queue draw to load changes (needed for groups)
             self.queue_draw(qh);
         }
         self.handle_new_duration(&wallpaper_info, handle, path_changed);
@@ -542,9 +1011,20 @@ impl Surface {
             if !path_changed {
                 // We should draw immediately
                 self.try_drawing(qh);
+                self.check_decoded_image(qh);
             }
         }
-        if self.wallpaper_info.transition != wallpaper_info.transition {
+        if self.wallpaper_info.color_profile != wallpaper_info.color_profile && !path_changed {
+            self.check_decoded_image(qh);
+        }
+        if self.wallpaper_info.background != wallpaper_info.background && !path_changed {
+            self.load_new_wallpaper();
+            self.queue_draw(qh);
+        }
+        if self.wallpaper_info.transition != wallpaper_info.transition
+            || self.wallpaper_info.random_transitions != wallpaper_info.random_transitions
+        {
+            self.random_transition = None;
             if let Some(context) = self.context.as_mut() {
                 let name = self.display_info.name.clone();
                 if let Err(err) = context
@@ -560,6 +1040,24 @@ impl Surface {
                 }
             }
         }
+        if self.wallpaper_info.effects != wallpaper_info.effects {
+            if let Some(context) = self.context.as_mut() {
+                if let Err(err) = context
+                    .make_current()
+                    .and_then(|_| {
+                        context
+                            .renderer
+                            .update_effects(&self.wallpaper_info.effects, &self.display_info)
+                    })
+                    .wrap_err_with(|| {
+                        format!("Failed to change the effects for display {}", self.name())
+                    })
+                {
+                    error!("{err:?}");
+                }
+            }
+            self.queue_draw(qh);
+        }
         if self.wallpaper_info.drawn_images_queue_size != wallpaper_info.drawn_images_queue_size {
             self.image_picker
                 .update_queue_size(self.wallpaper_info.drawn_images_queue_size);
@@ -671,7 +1169,7 @@ impl Surface {
         };
         let Some(duration) = duration else { return };
 
-        let timer = Timer::from_duration(duration);
+        let timer = Timer::from_duration(timer_wakeup(duration, self.wallpaper_info.prefetch));
 
         let name = self.name().to_owned();
         let registration_token = handle
@@ -701,6 +1199,10 @@ impl Surface {
                             let duration = if let Some(duration_left) =
                                 remaining_duration(duration, instant)
                             {
+                                // The timer woke up early to decode the next image in advance
+                                if duration_left <= surface.wallpaper_info.prefetch {
+                                    surface.prefetch_next_image();
+                                }
                                 duration_left
                             } else {
                                 // otherwise get the next image and set the new duration
@@ -724,7 +1226,10 @@ impl Surface {
                             };
                             surface.event_source =
                                 EventSource::Running(registration_token, duration, Instant::now());
-                            TimeoutAction::ToDuration(duration)
+                            TimeoutAction::ToDuration(timer_wakeup(
+                                duration,
+                                surface.wallpaper_info.prefetch,
+                            ))
                         }
                         EventSource::NotSet => TimeoutAction::Drop,
                         _ => unreachable!("timer must be running"),
@@ -778,12 +1283,20 @@ impl Surface {
         }
     }
 
+    /// Show `background` until the next or previous wallpaper is requested, like `wpaperctl set`
+    /// does for an image
+    pub fn set_background(&mut self, background: Background) {
+        self.forced_background = Some(background);
+        self.image_picker.start_detour();
+    }
+
     /// Indicate to the main event loop that the automatic wallpaper sequence for this [`Surface`]
     /// should be paused (explicit user request).
     /// The actual pausing/resuming is handled in [`Surface::handle_pause_state`]
     #[inline]
     pub fn pause(&mut self) {
         self.pause_reason = Some(PauseReason::User);
+        self.state_changed = true;
     }
 
     /// Pause cycling due to `wpaperctl set` - will be auto-resumed by next/previous.
@@ -793,6 +1306,7 @@ impl Surface {
     pub fn pause_for_set(&mut self) {
         if self.pause_reason.is_none() {
             self.pause_reason = Some(PauseReason::Set);
+            self.state_changed = true;
         }
     }
 
@@ -802,6 +1316,7 @@ impl Surface {
     #[inline]
     pub fn resume(&mut self) {
         self.pause_reason = None;
+        self.state_changed = true;
     }
 
     /// Toggle the pause state for this [`Surface`], which is responsible for indicating to the main
@@ -861,6 +1376,28 @@ impl Surface {
         }
     }
 
+    /// Return the time left on the timer of the previous run, if any.
+    /// Only the first timer added should use it.
+    #[inline]
+    pub fn take_restored_duration(&mut self) -> Option<Duration> {
+        self.restored_duration.take()
+    }
+
+    /// Return the state of this surface, to be restored by the next run of wpaperd
+    pub fn state(&self) -> OutputState {
+        OutputState {
+            image_picker: self.image_picker.state(),
+            pause_reason: self.pause_reason,
+            duration_left: self.get_remaining_duration(),
+        }
+    }
+
+    /// Return true if the state changed since the last call
+    #[inline]
+    pub fn take_state_changed(&mut self) -> bool {
+        std::mem::take(&mut self.state_changed)
+    }
+
     /// Compute the symlink path for the current wallpaper link.
     ///
     /// If `symlink` is set, `%PORT%` and `%NAME%` are expanded with the display's port name and
@@ -963,6 +1500,15 @@ impl Surface {
 
 impl Drop for Surface {
     fn drop(&mut self) {
+        if let Some(frame_timer) = self.frame_timer.take() {
+            self.event_loop_handle.remove(frame_timer);
+        }
+        if let Some(prefetching) = self.prefetching.take() {
+            self.image_loader
+                .borrow_mut()
+                .cancel(&prefetching, &self.display_info.name);
+        }
+
         // Do not leave any symlink when a surface gets destroyed.
         // Use remove_file directly rather than checking link.exists() first:
         // exists() follows symlinks and returns false for dangling symlinks
@@ -974,14 +1520,23 @@ impl Drop for Surface {
             if err.kind() != std::io::ErrorKind::NotFound {
                 warn!(
                     "{:?}",
-                    eyre!(err)
-                        .wrap_err(format!("Failed to remove symlink {link:?}"))
+                    eyre!(err).wrap_err(format!("Failed to remove symlink {link:?}"))
                 );
             }
         }
     }
 }
 
+/// Return when the timer of `duration` should wake up, so that the next image can be decoded
+/// `prefetch` before it's shown
+fn timer_wakeup(duration: Duration, prefetch: Duration) -> Duration {
+    if duration > prefetch {
+        duration - prefetch
+    } else {
+        duration
+    }
+}
+
 fn remaining_duration(duration: Duration, image_changed: Instant) -> Option<Duration> {
     let diff = image_changed.elapsed();
 
@@ -0,0 +1,131 @@
+use std::{
+    fs,
+    path::Path,
+    sync::{Arc, OnceLock},
+};
+
+use color_eyre::{
+    eyre::{eyre, OptionExt, WrapErr},
+    Result,
+};
+use image::{DynamicImage, RgbaImage};
+use resvg::{
+    tiny_skia::{Pixmap, Transform},
+    usvg::{fontdb::Database, Options, Tree},
+};
+
+use crate::{
+    filelist_cache::Dimensions,
+    image_loader::{DecodeLimits, TargetSize},
+};
+
+/// The system fonts are only loaded once, the first time an SVG is parsed
+static FONTS: OnceLock<Arc<Database>> = OnceLock::new();
+
+fn parse(path: &Path) -> Result<Tree> {
+    let data = fs::read(path).wrap_err_with(|| format!("Failed to read the SVG {path:?}"))?;
+    let options = Options {
+        resources_dir: path.parent().map(Path::to_path_buf),
+        fontdb: FONTS
+            .get_or_init(|| {
+                let mut fonts = Database::new();
+                fonts.load_system_fonts();
+                Arc::new(fonts)
+            })
+            .clone(),
+        ..Options::default()
+    };
+    Tree::from_data(&data, &options).wrap_err_with(|| format!("Failed to parse the SVG {path:?}"))
+}
+
+/// Return the size declared by the SVG, rounded up
+pub fn dimensions(path: &Path) -> Option<Dimensions> {
+    let size = parse(path).ok()?.size().to_int_size();
+    Some((size.width(), size.height()))
+}
+
+/// Rasterize the SVG at `path` to cover or fit `size`. The SVG keeps its own size when `size`
+/// is [TargetSize::Original]
+pub fn rasterize(path: &Path, size: TargetSize, limits: &DecodeLimits) -> Result<DynamicImage> {
+    let tree = parse(path)?;
+    let svg_size = tree.size();
+    let original = svg_size.to_int_size();
+    let (width, height) = size
+        .scale(original.width(), original.height())
+        .unwrap_or((original.width(), original.height()));
+    limits.check_dimensions(width, height)?;
+    let mut pixmap = Pixmap::new(width, height)
+        .ok_or_else(|| eyre!("Invalid size {width}x{height} for the SVG {path:?}"))?;
+    resvg::render(
+        &tree,
+        Transform::from_scale(
+            width as f32 / svg_size.width(),
+            height as f32 / svg_size.height(),
+        ),
+        &mut pixmap.as_mut(),
+    );
+    // tiny-skia works with premultiplied alpha
+    let pixels = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let color = pixel.demultiply();
+            [color.red(), color.green(), color.blue(), color.alpha()]
+        })
+        .collect();
+    RgbaImage::from_raw(width, height, pixels)
+        .map(DynamicImage::ImageRgba8)
+        .ok_or_eyre("The rasterized SVG has an invalid size")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_rasterize() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("image.svg");
+        fs::write(
+            &path,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10">
+                <rect width="20" height="10" fill="red"/>
+            </svg>"#,
+        )
+        .unwrap();
+
+        let limits = DecodeLimits {
+            max_pixels: 200 * 100,
+            max_memory: u64::MAX,
+            timeout: Duration::MAX,
+        };
+        assert_eq!(dimensions(&path), Some((20, 10)));
+        let original = rasterize(&path, TargetSize::Original, &limits).unwrap();
+        assert_eq!((original.width(), original.height()), (20, 10));
+        // SVGs are scaled up too
+        let image = rasterize(
+            &path,
+            TargetSize::Cover {
+                width: 100,
+                height: 100,
+            },
+            &limits,
+        )
+        .unwrap();
+        assert_eq!((image.width(), image.height()), (200, 100));
+        assert_eq!(image.as_bytes()[..4], [255, 0, 0, 255]);
+        // Too many pixels
+        assert!(rasterize(
+            &path,
+            TargetSize::Cover {
+                width: 200,
+                height: 200,
+            },
+            &limits,
+        )
+        .is_err());
+    }
+}
@@ -7,24 +7,35 @@ use std::{
 
 use smithay_client_toolkit::reexports::client::{protocol::wl_surface::WlSurface, QueueHandle};
 
-use crate::{image_picker::Queue, wpaperd::Wpaperd};
+use crate::{
+    image_picker::{ImagePickerSorting, Upcoming},
+    wpaperd::Wpaperd,
+};
 
 pub struct WallpaperGroup {
     pub group: u8,
     pub current_image: PathBuf,
     pub loading_image: Option<PathBuf>,
     pub surfaces: HashSet<WlSurface>,
-    pub queue: Queue,
+    /// Sorting shared by all the displays of the group, so that they move together
+    pub sorting: Rc<RefCell<ImagePickerSorting>>,
+    /// The next image of the group, picked in advance
+    pub upcoming: Rc<RefCell<Option<Upcoming>>>,
+    /// The sorting has been restored from the state of a display. The displays connected
+    /// afterwards follow the group instead of restoring their own, older, state
+    pub restored: bool,
 }
 
 impl WallpaperGroup {
-    pub fn new(group: u8, queue_size: usize) -> Self {
+    pub fn new(group: u8, sorting: ImagePickerSorting) -> Self {
         Self {
             group,
             current_image: PathBuf::from(""),
             loading_image: None,
             surfaces: HashSet::new(),
-            queue: Queue::with_capacity(queue_size),
+            sorting: Rc::new(RefCell::new(sorting)),
+            upcoming: Rc::new(RefCell::new(None)),
+            restored: false,
         }
     }
 
@@ -47,15 +58,17 @@ impl WallpaperGroups {
         }
     }
 
+    /// Add `wl_surface` to `group`. The group is created with the sorting returned by `sorting`
+    /// if it doesn't exist yet
     pub fn get_or_insert(
         &mut self,
         group: u8,
         wl_surface: &WlSurface,
-        queue_size: usize,
+        sorting: impl FnOnce() -> ImagePickerSorting,
     ) -> Rc<RefCell<WallpaperGroup>> {
         self.groups
             .entry(group)
-            .or_insert_with(|| Rc::new(RefCell::new(WallpaperGroup::new(group, queue_size))));
+            .or_insert_with(|| Rc::new(RefCell::new(WallpaperGroup::new(group, sorting()))));
         let wp_group = self.groups.get_mut(&group).unwrap();
         wp_group.borrow_mut().surfaces.insert(wl_surface.clone());
         wp_group.clone()
@@ -1,8 +1,13 @@
-use std::{path::PathBuf, time::Duration};
+use std::{path::PathBuf, str::FromStr, time::Duration};
 
 use serde::Deserialize;
 
-use crate::{image_picker::ImagePicker, render::Transition};
+use crate::{
+    config::RandomTransition,
+    image_loader::ImageLoader,
+    image_picker::ImagePicker,
+    render::{Effect, Transition},
+};
 
 #[derive(Debug, PartialEq, Default, Ord, Eq, PartialOrd, Clone, Copy)]
 pub enum Recursive {
@@ -23,12 +28,23 @@ impl From<bool> for Recursive {
 
 #[derive(PartialEq, Debug)]
 pub struct WallpaperInfo {
+    /// Empty when a background is set
     pub path: PathBuf,
+    /// Solid color or gradient shown instead of the images
+    pub background: Option<Background>,
     pub duration: Option<Duration>,
+    /// Decode the next image this long before the duration expires
+    pub prefetch: Duration,
     pub apply_shadow: bool,
     pub sorting: Option<Sorting>,
+    /// Displays in the same group show the same wallpaper and share the sorting
+    pub group: Option<u8>,
+    /// Key used to order the images when sorting is ascending or descending
+    pub sort_by: SortKey,
     pub mode: BackgroundMode,
     pub drawn_images_queue_size: usize,
+    /// How many times a favorite image is more likely to be picked by random sorting
+    pub favorites_weight: u32,
     pub transition_time: u32,
 
     /// Determines if we should show the transition between black and first
@@ -36,6 +52,11 @@ pub struct WallpaperInfo {
     /// `true` means we fade from black to the first wallpaper.
     pub initial_transition: bool,
     pub transition: Transition,
+    /// Pick one of these transitions, with its time, each time the wallpaper changes. Empty when
+    /// only `transition` is used
+    pub random_transitions: Vec<(RandomTransition, u32)>,
+    /// Applied in order to the wallpaper, also while a transition is running
+    pub effects: Vec<Effect>,
 
     /// Determine the offset for the wallpaper to be drawn into the screen
     /// Must be from 0.0 to 1.0, by default is 0.0 in tile mode and 0.5 in all the others
@@ -43,29 +64,58 @@ pub struct WallpaperInfo {
 
     /// Recursively iterate the directory set as path
     pub recursive: Option<Recursive>,
+
+    /// Only pick images with the same orientation (landscape or portrait) of the display
+    pub match_orientation: bool,
+    /// Only pick images whose aspect ratio differs at most by this fraction from the display one
+    pub aspect_tolerance: Option<f32>,
+    /// Only pick images at least this big
+    pub min_resolution: Option<MinResolution>,
     pub exec: Option<PathBuf>,
 
     /// Path for a symlink pointing to the current wallpaper.
     /// Relative paths are resolved under $XDG_STATE_HOME/wpaperd/wallpapers/.
     pub symlink: Option<PathBuf>,
+
+    /// Playback speed of animated images, 1.0 is the original speed
+    pub animation_speed: f32,
+    /// Maximum number of frames per second shown for animated images
+    pub animation_max_fps: Option<u32>,
+
+    /// ICC profile of the display, the images are converted from their own profile to it.
+    /// sRGB is used when it's not set
+    pub color_profile: Option<PathBuf>,
 }
 
 impl Default for WallpaperInfo {
     fn default() -> Self {
         Self {
             path: PathBuf::new(),
+            background: None,
             duration: None,
+            prefetch: ImageLoader::DEFAULT_PREFETCH,
             apply_shadow: false,
             sorting: None,
+            group: None,
+            sort_by: SortKey::default(),
             mode: BackgroundMode::default(),
             drawn_images_queue_size: ImagePicker::DEFAULT_DRAWN_IMAGES_QUEUE_SIZE,
+            favorites_weight: 1,
             transition_time: Transition::Fade {}.default_transition_time(),
             initial_transition: true,
             transition: Transition::Fade {},
+            random_transitions: Vec::new(),
+            effects: Vec::new(),
             offset: None,
             recursive: None,
+            match_orientation: false,
+            aspect_tolerance: None,
+            min_resolution: None,
             exec: None,
             symlink: None,
+            animation_speed: 1.0,
+            animation_max_fps: None,
+            color_profile: None,
         }
     }
 }
@@ -75,12 +125,109 @@ impl Default for WallpaperInfo {
 pub enum Sorting {
     #[default]
     Random,
-    #[serde(skip)]
-    GroupedRandom {
-        group: u8,
-    },
     Ascending,
     Descending,
+    /// Show every image once in a random order before reshuffling
+    Shuffle,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MinResolution {
+    /// The resolution of the display
+    Native,
+    Size {
+        width: u32,
+        height: u32,
+    },
+}
+
+impl FromStr for MinResolution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "native" {
+            return Ok(Self::Native);
+        }
+        s.split_once('x')
+            .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+            .map(|(width, height)| Self::Size { width, height })
+            .ok_or_else(|| format!("invalid resolution {s:?}, expected \"native\" or WIDTHxHEIGHT"))
+    }
+}
+
+/// An opaque sRGB color, written as #RRGGBB
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Color(pub [u8; 3]);
+
+impl Color {
+    pub fn rgba(self) -> [u8; 4] {
+        let [r, g, b] = self.0;
+        [r, g, b, u8::MAX]
+    }
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        let channel = |i: usize| {
+            hex.get(i..i + 2)
+                .and_then(|channel| u8::from_str_radix(channel, 16).ok())
+        };
+        match (hex.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(r), Some(g), Some(b)) => Ok(Self([r, g, b])),
+            _ => Err(format!("invalid color {s:?}, expected #RRGGBB")),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Gradient {
+    pub from: Color,
+    pub to: Color,
+    /// Direction of the gradient in degrees, like in CSS: 0 goes upwards and 90 to the right
+    #[serde(default = "Gradient::default_angle")]
+    pub angle: f32,
+}
+
+impl Gradient {
+    pub fn default_angle() -> f32 {
+        180.0
+    }
+}
+
+/// A background generated without any image file
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Background {
+    Color(Color),
+    Gradient(Gradient),
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SortKey {
+    /// Order by file name, character by character
+    #[default]
+    Name,
+    /// Order by file name, comparing numbers by their value (img2 comes before img10)
+    Natural,
+    /// Order by the last modification time of the file
+    Mtime,
+    /// Order by the EXIF DateTimeOriginal tag, images without it come last
+    Exif,
 }
 
 #[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
@@ -92,4 +239,6 @@ pub enum BackgroundMode {
     Fit,
     Tile,
     FitBorderColor,
+    /// Show a single image across all the displays of the same group
+    Span,
 }
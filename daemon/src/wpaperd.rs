@@ -23,9 +23,10 @@ use smithay_client_toolkit::{
 use xdg::BaseDirectories;
 
 use crate::config::Config;
-use crate::display_info::DisplayInfo;
+use crate::display_info::{DisplayInfo, SpanArea};
 use crate::filelist_cache::FilelistCache;
 use crate::image_loader::ImageLoader;
+use crate::state::State;
 use crate::surface::Surface;
 use crate::wallpaper_groups::WallpaperGroups;
 use crate::wallpaper_info::WallpaperInfo;
@@ -43,6 +44,10 @@ pub struct Wpaperd {
     pub image_loader: Rc<RefCell<ImageLoader>>,
     pub wallpaper_groups: Rc<RefCell<WallpaperGroups>>,
     pub xdg_dirs: BaseDirectories,
+    /// Used by the surfaces to add the timers of the animations and the shaders
+    pub event_loop_handle: LoopHandle<'static, Wpaperd>,
+    /// State of the outputs, restored at startup and saved when it changes
+    pub state: State,
     /// True when running under KDE Plasma (org_kde_plasma_shell global is present).
     /// KDE Plasma also occupies the background layer; with an empty input region,
     /// clicks fall through to the Plasma desktop and trigger its wallpaper manager.
@@ -51,6 +56,7 @@ pub struct Wpaperd {
 }
 
 impl Wpaperd {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         qh: &QueueHandle<Self>,
         globals: &GlobalList,
@@ -59,6 +65,7 @@ impl Wpaperd {
         filelist_cache: Rc<RefCell<FilelistCache>>,
         image_loader: Rc<RefCell<ImageLoader>>,
         xdg_dirs: BaseDirectories,
+        event_loop_handle: LoopHandle<'static, Wpaperd>,
     ) -> Result<Self> {
         let shm_state = Shm::bind(globals, qh).wrap_err("Failed to bind memory state")?;
 
@@ -67,6 +74,20 @@ impl Wpaperd {
             .contents()
             .with_list(|list| list.iter().any(|g| g.interface == "org_kde_plasma_shell"));
 
+        let state = match xdg_dirs
+            .place_state_file(State::FILENAME)
+            .wrap_err("Failed to create the state directory $XDG_STATE_HOME/wpaperd")
+        {
+            Ok(path) => State::load(path),
+            Err(err) => {
+                warn!(
+                    "{:?}",
+                    err.wrap_err("The state of the displays will not be saved")
+                );
+                State::default()
+            }
+        };
+
         Ok(Self {
             compositor_state: CompositorState::bind(globals, qh)
                 .wrap_err("Failed to bind compositor state")?,
@@ -82,10 +103,83 @@ impl Wpaperd {
             image_loader,
             wallpaper_groups: Rc::new(RefCell::new(WallpaperGroups::new())),
             xdg_dirs,
+            event_loop_handle,
+            state,
             is_kde,
         })
     }
 
+    /// Save the state of all the surfaces to disk
+    pub fn save_state(&mut self) {
+        for surface in &mut self.surfaces {
+            surface.take_state_changed();
+            self.state.update(surface.name(), surface.state());
+        }
+        if let Err(err) = self.state.save() {
+            error!("{err:?}");
+        }
+        // The images set outside of the wallpaper directories stay in the index while shown
+        let current: Vec<_> = self
+            .surfaces
+            .iter()
+            .map(|surface| surface.image_picker.current_image())
+            .collect();
+        self.filelist_cache.borrow_mut().forget_unwatched(&current);
+    }
+
+    /// Save the state to disk if the state of any surface changed
+    pub fn save_state_if_changed(&mut self) {
+        // save_state resets the flag of every surface, short-circuiting is fine
+        if self
+            .surfaces
+            .iter_mut()
+            .any(|surface| surface.take_state_changed())
+        {
+            self.save_state();
+        }
+    }
+
+    /// Pick a wallpaper for the displays that are not showing an image from their path yet,
+    /// because the directory was still being read
+    pub fn load_missing_wallpapers(&mut self) {
+        for surface in self.surfaces.iter_mut().filter(|surface| {
+            !surface
+                .image_picker
+                .current_image()
+                .starts_with(&surface.wallpaper_info.path)
+        }) {
+            surface.image_picker.next_image();
+            surface.load_new_wallpaper();
+        }
+    }
+
+    /// Compute the area covered by each display spanning a wallpaper, relative to the bounding
+    /// box of all the displays in the same group
+    pub fn update_span_areas(&mut self, qh: &QueueHandle<Wpaperd>) {
+        let geometries: Vec<_> = self
+            .surfaces
+            .iter()
+            .map(|surface| {
+                surface.span_group().and_then(|group| {
+                    let info = self.output_state.info(surface.wl_output())?;
+                    let (x, y) = info.logical_position?;
+                    let (width, height) = info.logical_size?;
+                    Some(SpanGeometry {
+                        group,
+                        x,
+                        y,
+                        width,
+                        height,
+                    })
+                })
+            })
+            .collect();
+
+        for (surface, span_area) in self.surfaces.iter_mut().zip(span_areas(&geometries)) {
+            surface.change_span_area(span_area, qh);
+        }
+    }
+
     pub fn update_surfaces(&mut self, ev_handle: LoopHandle<Wpaperd>, qh: &QueueHandle<Wpaperd>) {
         for surface in &mut self.surfaces {
             let res = self
@@ -140,10 +234,12 @@ impl CompositorHandler for Wpaperd {
         _conn: &Connection,
         qh: &QueueHandle<Self>,
         surface: &wl_surface::WlSurface,
-        _time: u32,
+        time: u32,
     ) {
         if let Some(s) = self.surface_from_wl_surface(surface) {
-            s.try_drawing(qh);
+            if s.new_frame(time) {
+                s.try_drawing(qh);
+            }
         }
     }
 
@@ -310,7 +406,12 @@ impl OutputHandler for Wpaperd {
             .find(|(_, surface)| *surface.wl_output() == output)
         {
             Some((index, _)) => {
-                self.surfaces.swap_remove(index);
+                let surface = self.surfaces.swap_remove(index);
+                // Keep its state, so that it can be restored if the display comes back
+                self.state.update(surface.name(), surface.state());
+                if let Err(err) = self.state.save() {
+                    error!("{err:?}");
+                }
             }
             None => {
                 // get name of display using xdg
@@ -378,3 +479,99 @@ impl ProvidesRegistryState for Wpaperd {
     }
     registry_handlers![OutputState];
 }
+
+/// Position and size of a display in the global compositor space, in logical pixels. They
+/// already take into account the scale and the transform of the display
+#[derive(Debug, Clone, Copy)]
+struct SpanGeometry {
+    group: u8,
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+}
+
+/// Return the area of each display spanning a wallpaper inside the bounding box of its group
+fn span_areas(geometries: &[Option<SpanGeometry>]) -> Vec<Option<SpanArea>> {
+    let geometries: Vec<_> = geometries
+        .iter()
+        .map(|geometry| geometry.filter(|g| g.width > 0 && g.height > 0))
+        .collect();
+    geometries
+        .iter()
+        .map(|geometry| {
+            let geometry = (*geometry)?;
+            let group: Vec<_> = geometries
+                .iter()
+                .flatten()
+                .filter(|other| other.group == geometry.group)
+                .collect();
+            // A display alone in its group shows the image as a whole
+            if group.len() < 2 {
+                return None;
+            }
+            let min_x = group.iter().map(|g| g.x).min()?;
+            let min_y = group.iter().map(|g| g.y).min()?;
+            let max_x = group.iter().map(|g| g.x + g.width).max()?;
+            let max_y = group.iter().map(|g| g.y + g.height).max()?;
+            let canvas_width = (max_x - min_x) as f32;
+            let canvas_height = (max_y - min_y) as f32;
+            Some(SpanArea {
+                x: (geometry.x - min_x) as f32 / canvas_width,
+                y: (geometry.y - min_y) as f32 / canvas_height,
+                width: geometry.width as f32 / canvas_width,
+                height: geometry.height as f32 / canvas_height,
+                canvas_ratio: canvas_width / canvas_height,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_span_areas() {
+        let geometry = |group, x, y, width, height| {
+            Some(SpanGeometry {
+                group,
+                x,
+                y,
+                width,
+                height,
+            })
+        };
+        let areas = span_areas(&[
+            // 1920x1080 at scale 1
+            geometry(0, 0, 0, 1920, 1080),
+            // 3840x2160 at scale 2, rotated by 90 degrees
+            geometry(0, 1920, 0, 1080, 1920),
+            // Alone in its group
+            geometry(1, 3000, 0, 1920, 1080),
+            None,
+        ]);
+        let canvas_ratio = 3000.0 / 1920.0;
+        assert_eq!(
+            areas,
+            vec![
+                Some(SpanArea {
+                    x: 0.0,
+                    y: 0.0,
+                    width: 0.64,
+                    height: 0.5625,
+                    canvas_ratio,
+                }),
+                Some(SpanArea {
+                    x: 0.64,
+                    y: 0.0,
+                    width: 0.36,
+                    height: 1.0,
+                    canvas_ratio,
+                }),
+                None,
+                None,
+            ]
+        );
+    }
+}
@@ -34,22 +34,54 @@ pub enum IpcMessage {
         path: PathBuf,
         monitors: Vec<String>,
     },
+    /// Show a solid color, or a gradient from `color` to `to`, instead of the wallpaper
+    SetColor {
+        color: String,
+        to: Option<String>,
+        angle: Option<f32>,
+        monitors: Vec<String>,
+    },
+    /// Add the current wallpaper of the monitors to the favorites
+    Favorite {
+        monitors: Vec<String>,
+    },
+    /// Remove the current wallpaper of the monitors from the favorites
+    Unfavorite {
+        monitors: Vec<String>,
+    },
+    /// Never show the current wallpaper of the monitors again
+    Ban {
+        monitors: Vec<String>,
+    },
+    /// Read all the wallpaper directories again
+    Rescan,
 }
 
 #[derive(Serialize, Deserialize)]
 pub enum IpcResponse {
-    CurrentWallpaper {
-        path: PathBuf,
-    },
-    AllWallpapers {
-        entries: Vec<(String, PathBuf)>,
-    },
-    DisplaysStatus {
-        entries: Vec<(String, String, Option<Duration>)>,
-    },
+    CurrentWallpaper { path: PathBuf },
+    AllWallpapers { entries: Vec<(String, PathBuf)> },
+    DisplaysStatus { entries: Vec<DisplayStatus> },
     Ok,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct DisplayStatus {
+    pub display: String,
+    pub status: String,
+    pub duration_left: Option<Duration>,
+    /// Number of images excluded by the filters of the display configuration
+    pub excluded_images: usize,
+    /// The directory of the display is being read
+    pub scanning: bool,
+    /// Number of images shown by the display that were already decoded
+    pub cache_hits: u64,
+    /// Number of images shown by the display that had to be decoded
+    pub cache_misses: u64,
+    /// The last images rejected because they exceeded the decoding limits, with the reason
+    pub rejected_images: Vec<(PathBuf, String)>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum IpcError {
     MonitorNotFound {
@@ -58,6 +90,11 @@ pub enum IpcError {
     DrawErrors(Vec<(String, String)>),
     /// Validation error for invalid input (e.g., file not found, not an image)
     ValidationError(String),
+    /// The favorites or banned list could not be saved after changing `path`
+    SaveError {
+        path: PathBuf,
+        error: String,
+    },
 }
 
 pub fn socket_path() -> Result<PathBuf, BaseDirectoriesError> {